@@ -0,0 +1,202 @@
+/* an append-only log of every operation that changes state, so employers and applicants can see who did what.
+   events are never changed or removed once written */
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::log::Log as StableLog;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+
+use crate::config::ensure_moderator;
+use crate::{find_application, get_job, is_employer, migrations, Error, Memory, MEMORY_MANAGER};
+
+const DEFAULT_AUDIT_PAGE_SIZE: u32 = 50;
+const MAX_AUDIT_PAGE_SIZE: u32 = 100;
+
+//the schema version new events are written with, see migrations.rs
+const AUDIT_VERSION: u8 = 1;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEvent {
+    id: u64,                    // the position of the event in the log
+    caller: Principal,          // the canister itself for timers
+    timestamp: u64,
+    operation: Operation,
+    job_id: Option<u64>,
+    target_id: Option<u64>,     // the application, milestone, dispute, company or blob the operation acted on
+    subject: Option<Principal>, // the principal the operation was about, like the applicant that was hired
+}
+
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum Operation {
+    JobCreated,
+    JobUpdated,
+    JobPublished,
+    JobClosed,
+    JobCancelled,
+    JobCompleted,
+    JobExpired,
+    ApplicationLimitReached,
+    DeadlineChanged,
+    ApplicationSubmitted,
+    ApplicationWithdrawn,
+    ApplicationStatusChanged,
+    ApplicantHired,
+    MilestoneAdded,
+    MilestoneDelivered,
+    MilestoneReleased,
+    AutoApprovalAbandoned,
+    ReviewWindowChanged,
+    DisputeOpened,
+    EvidenceSubmitted,
+    DisputeVoteCast,
+    DisputeSettled,
+    DisputeDeadlockBroken,
+    CompanyCreated,
+    CompanyUpdated,
+    CompanyOwnerAdded,
+    CompanyOwnerRemoved,
+    CompanyVerified,
+    ProfileCreated,
+    ProfileUpdated,
+    ProfileDeleted,
+    UploadStarted,
+    UploadCommitted,
+    UploadCancelled,
+    BlobDeleted,
+    LedgerSet,
+    ArbiterAdded,
+    ArbiterRemoved,
+    RateLimitsChanged,
+    ConfigChanged,
+    AdminAdded,
+    AdminRemoved,
+    ModeratorAdded,
+    ModeratorRemoved,
+}
+
+impl Operation {
+    fn targets_application(&self) -> bool {
+        matches!(
+            self,
+            Operation::ApplicationSubmitted
+                | Operation::ApplicationWithdrawn
+                | Operation::ApplicationStatusChanged
+                | Operation::ApplicantHired
+        )
+    }
+}
+
+//one page of events, next_cursor is None once there is nothing left to read
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditPage {
+    events: Vec<AuditEvent>,
+    next_cursor: Option<u64>,
+}
+
+impl Storable for AuditEvent {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(AUDIT_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("AuditEvent", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static EVENTS: RefCell<StableLog<AuditEvent, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))),
+        ).expect("cannot create audit log")
+    );
+
+    /*the events of each job, keyed by (job_id, event id)*/
+    static JOB_EVENTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+        ));
+}
+
+/* appends an event for an operation the caller just completed */
+pub(crate) fn record(operation: Operation, job_id: Option<u64>, target_id: Option<u64>, subject: Option<Principal>) {
+    EVENTS.with(|events| {
+        let events = events.borrow();
+        let id = events.len();
+        let event = AuditEvent { id, caller: caller(), timestamp: time(), operation, job_id, target_id, subject };
+        events.append(&event).expect("cannot append to the audit log");
+
+        if let Some(job_id) = job_id {
+            JOB_EVENTS.with(|index| index.borrow_mut().insert((job_id, id), ()));
+        }
+    });
+}
+
+fn page_size(limit: Option<u32>) -> usize {
+    limit.unwrap_or(DEFAULT_AUDIT_PAGE_SIZE).clamp(1, MAX_AUDIT_PAGE_SIZE) as usize
+}
+
+/* the whole log, oldest first, for moderators and admins */
+#[ic_cdk::query]
+fn list_audit_events(cursor: Option<u64>, limit: Option<u32>) -> Result<AuditPage, Error> {
+    ensure_moderator("read the audit log")?;
+
+    EVENTS.with(|events| {
+        let events = events.borrow();
+        let start = cursor.unwrap_or(0);
+        let end = events.len().min(start.saturating_add(page_size(limit) as u64));
+
+        Ok(AuditPage {
+            events: (start..end).filter_map(|id| events.get(id)).collect(),
+            next_cursor: (end < events.len()).then_some(end),
+        })
+    })
+}
+
+/* the history of a job, oldest first. the employer sees every event, an applicant sees the events
+   on the job itself and on their own application */
+#[ic_cdk::query]
+fn job_history(job_id: u64, cursor: Option<u64>, limit: Option<u32>) -> Result<AuditPage, Error> {
+    let job = get_job(job_id)?;
+    let who = caller();
+    let own_application = if is_employer(&job, &who) {
+        None
+    } else {
+        let application = find_application(job_id, who).ok_or(Error::unauthorized("view the job's history"))?;
+        Some(application.id)
+    };
+    let visible = |event: &AuditEvent| match own_application {
+        None => true,
+        Some(application_id) => !event.operation.targets_application() || event.target_id == Some(application_id),
+    };
+
+    let limit = page_size(limit);
+    let start = cursor.unwrap_or(0);
+    let mut page = vec![];
+    let mut next_cursor = None;
+    JOB_EVENTS.with(|index| {
+        EVENTS.with(|events| {
+            let events = events.borrow();
+            for ((_, id), _) in index.borrow().range((job_id, start)..=(job_id, u64::MAX)) {
+                if page.len() == limit {
+                    next_cursor = Some(id);
+                    break;
+                }
+                if let Some(event) = events.get(id).filter(|event| visible(event)) {
+                    page.push(event);
+                }
+            }
+        })
+    });
+
+    Ok(AuditPage { events: page, next_cursor })
+}
@@ -0,0 +1,357 @@
+/* files such as resumes and portfolios, uploaded in chunks and kept in stable memory.
+   an upload is started with begin_upload, filled with put_chunk in order, and turned into a blob by commit_upload.
+   a blob can be read by its uploader and by the employers of the jobs it was attached to an application for */
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+
+use crate::audit::{self, Operation};
+use crate::{get_job, is_employer, migrations, validate_text, Error, IdCell, Memory, MEMORY_MANAGER};
+use crate::rate_limit::throttle;
+
+/* a chunk has to fit in one ingress message, which is at most 2 MiB */
+pub(crate) const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_BLOB_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_MIME_TYPE_LEN: usize = 100;
+
+/* how many bytes a principal may keep, counting uploads that are still in progress */
+const STORAGE_QUOTA: u64 = 50 * 1024 * 1024;
+
+pub(crate) const MAX_ATTACHMENTS: usize = 5;
+
+//the schema version new blobs and uploads are written with, see migrations.rs
+const BLOB_VERSION: u8 = 1;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Blob {
+    id: u64,
+    owner: Principal,
+    mime_type: String,
+    size: u64,
+    sha256: String, // hex encoded hash of the content
+    chunk_count: u32,
+    created_at: u64,
+}
+
+//an upload that has not been committed yet
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+struct Upload {
+    id: u64, // becomes the id of the blob
+    owner: Principal,
+    mime_type: String,
+    size: u64, // the size announced in begin_upload, reserved against the owner's quota
+    received: u64,
+    chunk_count: u32,
+    started_at: u64,
+}
+
+//how much of their quota a principal is using
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct StorageUsage {
+    used: u64,
+    quota: u64,
+}
+
+impl Storable for Blob {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(BLOB_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Blob", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Upload {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(BLOB_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Upload", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    /*the content of uploads and blobs, keyed by (blob_id, chunk index)*/
+    static CHUNKS: RefCell<StableBTreeMap<(u64, u32), Vec<u8>, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+        ));
+
+    static UPLOADS: RefCell<StableBTreeMap<u64, Upload, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+        ));
+
+    static BLOBS: RefCell<StableBTreeMap<u64, Blob, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        ));
+
+    /*bytes held by each principal, in blobs and in uploads in progress*/
+    static USAGE: RefCell<StableBTreeMap<Principal, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        ));
+
+    static BLOB_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))), 0).expect("cannot create blob counter")
+    );
+
+    /*the jobs each blob was attached to an application for, keyed by (blob_id, job_id)*/
+    static ATTACHMENTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+        ));
+}
+
+fn blob_not_found(blob_id: u64) -> Error {
+    Error::NotFound { resource: String::from("blob"), id: blob_id.to_string() }
+}
+
+fn upload_not_found(upload_id: u64) -> Error {
+    Error::NotFound { resource: String::from("upload"), id: upload_id.to_string() }
+}
+
+fn get_blob(blob_id: u64) -> Result<Blob, Error> {
+    BLOBS.with(|blobs| blobs.borrow().get(&blob_id)).ok_or(blob_not_found(blob_id))
+}
+
+/* an upload in progress, for its owner */
+fn get_upload(upload_id: u64) -> Result<Upload, Error> {
+    let upload = UPLOADS
+        .with(|uploads| uploads.borrow().get(&upload_id))
+        .ok_or(upload_not_found(upload_id))?;
+    if upload.owner != caller() {
+        return Err(Error::unauthorized("upload to this blob"));
+    }
+    Ok(upload)
+}
+
+fn usage(owner: Principal) -> u64 {
+    USAGE.with(|usage| usage.borrow().get(&owner).unwrap_or(0))
+}
+
+fn set_usage(owner: Principal, used: u64) {
+    USAGE.with(|usage| usage.borrow_mut().insert(owner, used));
+}
+
+fn remove_chunks(blob_id: u64, chunk_count: u32) {
+    CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        for index in 0..chunk_count {
+            chunks.remove(&(blob_id, index));
+        }
+    });
+}
+
+/* checks that the caller owns every blob they attach to an application */
+pub(crate) fn validate_attachments(attachments: &[u64]) -> Result<(), Error> {
+    if attachments.len() > MAX_ATTACHMENTS {
+        return Err(Error::CapacityExceeded { resource: String::from("attachments"), limit: MAX_ATTACHMENTS as u64 });
+    }
+    for blob_id in attachments {
+        if get_blob(*blob_id)?.owner != caller() {
+            return Err(Error::unauthorized("attach the blob"));
+        }
+    }
+    Ok(())
+}
+
+/* lets the employer of `job_id` read the attached blobs */
+pub(crate) fn attach(job_id: u64, attachments: &[u64]) {
+    ATTACHMENTS.with(|index| {
+        let mut index = index.borrow_mut();
+        for blob_id in attachments {
+            index.insert((*blob_id, job_id), ());
+        }
+    });
+}
+
+/* the uploader, and the employers of the jobs the blob was sent to with an application */
+fn ensure_can_read(blob: &Blob) -> Result<(), Error> {
+    let who = caller();
+    if blob.owner == who {
+        return Ok(());
+    }
+
+    let employer_of_job = ATTACHMENTS.with(|index| {
+        index
+            .borrow()
+            .range((blob.id, 0)..=(blob.id, u64::MAX))
+            .any(|((_, job_id), _)| get_job(job_id).is_ok_and(|job| is_employer(&job, &who)))
+    });
+    if employer_of_job {
+        Ok(())
+    } else {
+        Err(Error::unauthorized("read the blob"))
+    }
+}
+
+/* starts an upload of `size` bytes, which are reserved against the caller's quota until the upload is committed or cancelled */
+#[ic_cdk::update(guard = "throttle")]
+fn begin_upload(mime_type: String, size: u64) -> Result<u64, Error> {
+    validate_text("mime_type", &mime_type, true, MAX_MIME_TYPE_LEN)?;
+    if !mime_type.split_once('/').is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty()) {
+        return Err(Error::validation("mime_type", "must look like type/subtype"));
+    }
+    if size == 0 || size > MAX_BLOB_SIZE {
+        return Err(Error::validation("size", &format!("must be between 1 and {} bytes", MAX_BLOB_SIZE)));
+    }
+
+    let owner = caller();
+    let used = usage(owner);
+    if used.saturating_add(size) > STORAGE_QUOTA {
+        return Err(Error::CapacityExceeded { resource: String::from("storage bytes"), limit: STORAGE_QUOTA });
+    }
+
+    let id = BLOB_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).expect("Cannot increment blob id counter");
+        current_value + 1
+    });
+
+    let upload = Upload {
+        id,
+        owner,
+        mime_type: mime_type.to_lowercase(),
+        size,
+        received: 0,
+        chunk_count: 0,
+        started_at: time(),
+    };
+    UPLOADS.with(|uploads| uploads.borrow_mut().insert(id, upload));
+    set_usage(owner, used + size);
+    audit::record(Operation::UploadStarted, None, Some(id), None);
+
+    Ok(id)
+}
+
+/* appends a chunk to an upload. chunks are sent in order, starting at index 0 */
+#[ic_cdk::update(guard = "throttle")]
+fn put_chunk(upload_id: u64, index: u32, data: Vec<u8>) -> Result<(), Error> {
+    let mut upload = get_upload(upload_id)?;
+
+    if index != upload.chunk_count {
+        return Err(Error::validation("index", &format!("the next chunk is {}", upload.chunk_count)));
+    }
+    if data.is_empty() || data.len() > MAX_CHUNK_SIZE {
+        return Err(Error::validation("data", &format!("must be between 1 and {} bytes", MAX_CHUNK_SIZE)));
+    }
+    if upload.received + data.len() as u64 > upload.size {
+        return Err(Error::validation("data", "is more than the size given when the upload began"));
+    }
+
+    upload.received += data.len() as u64;
+    upload.chunk_count += 1;
+    CHUNKS.with(|chunks| chunks.borrow_mut().insert((upload_id, index), data));
+    UPLOADS.with(|uploads| uploads.borrow_mut().insert(upload_id, upload));
+    Ok(())
+}
+
+/* finishes an upload once all of its bytes have been received and returns the stored blob */
+#[ic_cdk::update(guard = "throttle")]
+fn commit_upload(upload_id: u64) -> Result<Blob, Error> {
+    let upload = get_upload(upload_id)?;
+    if upload.received != upload.size {
+        return Err(Error::InvalidState {
+            current: format!("{} of {} bytes received", upload.received, upload.size),
+            action: String::from("commit the upload"),
+        });
+    }
+
+    let mut hasher = Sha256::new();
+    CHUNKS.with(|chunks| {
+        for (_, data) in chunks.borrow().range((upload_id, 0)..=(upload_id, u32::MAX)) {
+            hasher.update(&data);
+        }
+    });
+    let sha256 = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let blob = Blob {
+        id: upload.id,
+        owner: upload.owner,
+        mime_type: upload.mime_type,
+        size: upload.size,
+        sha256,
+        chunk_count: upload.chunk_count,
+        created_at: time(),
+    };
+    BLOBS.with(|blobs| blobs.borrow_mut().insert(blob.id, blob.clone()));
+    UPLOADS.with(|uploads| uploads.borrow_mut().remove(&upload_id));
+    audit::record(Operation::UploadCommitted, None, Some(blob.id), None);
+    Ok(blob)
+}
+
+/* drops an upload that will not be committed and gives its bytes back to the caller's quota */
+#[ic_cdk::update(guard = "throttle")]
+fn cancel_upload(upload_id: u64) -> Result<(), Error> {
+    let upload = get_upload(upload_id)?;
+
+    remove_chunks(upload_id, upload.chunk_count);
+    UPLOADS.with(|uploads| uploads.borrow_mut().remove(&upload_id));
+    set_usage(upload.owner, usage(upload.owner).saturating_sub(upload.size));
+    audit::record(Operation::UploadCancelled, None, Some(upload_id), None);
+    Ok(())
+}
+
+/* deletes one of the caller's blobs. applications it was attached to keep its id but can no longer read it */
+#[ic_cdk::update(guard = "throttle")]
+fn delete_blob(blob_id: u64) -> Result<(), Error> {
+    let blob = get_blob(blob_id)?;
+    if blob.owner != caller() {
+        return Err(Error::unauthorized("delete the blob"));
+    }
+
+    remove_chunks(blob_id, blob.chunk_count);
+    BLOBS.with(|blobs| blobs.borrow_mut().remove(&blob_id));
+    ATTACHMENTS.with(|index| {
+        let mut index = index.borrow_mut();
+        let jobs: Vec<(u64, u64)> = index.range((blob_id, 0)..=(blob_id, u64::MAX)).map(|(key, _)| key).collect();
+        for key in jobs {
+            index.remove(&key);
+        }
+    });
+    set_usage(blob.owner, usage(blob.owner).saturating_sub(blob.size));
+    audit::record(Operation::BlobDeleted, None, Some(blob_id), None);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn fetch_blob(blob_id: u64) -> Result<Blob, Error> {
+    let blob = get_blob(blob_id)?;
+    ensure_can_read(&blob)?;
+    Ok(blob)
+}
+
+#[ic_cdk::query]
+fn fetch_blob_chunk(blob_id: u64, index: u32) -> Result<Vec<u8>, Error> {
+    let blob = get_blob(blob_id)?;
+    ensure_can_read(&blob)?;
+
+    CHUNKS
+        .with(|chunks| chunks.borrow().get(&(blob_id, index)))
+        .ok_or(Error::NotFound { resource: String::from("chunk"), id: index.to_string() })
+}
+
+#[ic_cdk::query]
+fn storage_usage() -> StorageUsage {
+    StorageUsage { used: usage(caller()), quota: STORAGE_QUOTA }
+}
@@ -0,0 +1,191 @@
+/* certified job data, so a client can check that a query answer really comes from the canister.
+
+   every job is a leaf in a hash tree (see hash_tree.rs), labeled with its id as 8 big-endian bytes and holding the
+   sha-256 hash of the candid encoding of the job. the root of the tree is certified under the label "jobs" with
+   set_certified_data, and is updated every time a job is saved.
+
+   candid has no canonical encoding, so a client cannot encode a job it decoded and expect the bytes that were
+   hashed. every certified response carries those bytes, job_bytes, next to the job instead.
+
+   to verify a response a client checks the certificate against the IC root key, computes the root hash of the
+   witness and compares labeled("jobs", that root) with the certified_data in the certificate, then looks up the
+   job's key in the witness, compares the leaf with sha256(job_bytes) and decodes job_bytes as a Job. the decoded
+   job is the one that is certified, not the `job` field next to it */
+use std::cell::RefCell;
+
+use candid::{CandidType, Encode};
+use sha2::{Digest, Sha256};
+
+use crate::hash_tree::{labeled_hash, Hash, HashTree, JobTree};
+use crate::{get_job, list_jobs, Error, Job, JobPage, ListJobs, STORAGE};
+
+const JOBS_LABEL: &[u8] = b"jobs";
+
+//a job with the proof that it is part of the certified state
+#[derive(CandidType, Clone, Deserialize)]
+pub(crate) struct CertifiedJob {
+    job: Job,
+    job_bytes: Vec<u8>,   // the candid encoding of the job whose hash is the job's leaf
+    certificate: Vec<u8>, // the IC certificate over the canister's certified data
+    witness: Vec<u8>,     // the CBOR encoded hash tree the job's leaf is part of
+}
+
+//a page of list_jobs with a witness covering every job id from the first to the last job on the page
+#[derive(CandidType, Clone, Deserialize)]
+pub(crate) struct CertifiedJobPage {
+    page: JobPage,
+    job_bytes: Vec<Vec<u8>>, // the hashed encoding of every job on the page, in the same order
+    certificate: Vec<u8>,
+    witness: Vec<u8>,
+}
+
+thread_local! {
+    /*kept on the heap and rebuilt from the stored jobs after an upgrade*/
+    static TREE: RefCell<JobTree> = RefCell::new(JobTree::default());
+}
+
+/* the bytes a job's leaf is the hash of */
+fn job_bytes(job: &Job) -> Vec<u8> {
+    Encode!(job).expect("failed to encode job")
+}
+
+fn leaf_hash(job: &Job) -> Hash {
+    Sha256::digest(job_bytes(job)).into()
+}
+
+fn certify_root(tree: &JobTree) {
+    // there is no system API outside a canister, unit tests only look at the tree
+    if cfg!(not(test)) {
+        ic_cdk::api::set_certified_data(&labeled_hash(JOBS_LABEL, &tree.root_hash()));
+    }
+}
+
+/* puts the current state of a job in the tree, called whenever a job is saved */
+pub(crate) fn certify(job: &Job) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert(job.id, leaf_hash(job));
+        certify_root(&tree);
+    });
+}
+
+/* certified data and the tree are lost on upgrade, so they are built again from stable memory */
+pub(crate) fn rebuild() {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        *tree = JobTree::default();
+        STORAGE.with(|storage| {
+            for (_, job) in storage.borrow().iter() {
+                tree.insert(job.id, leaf_hash(&job));
+            }
+        });
+        certify_root(&tree);
+    });
+}
+
+/* the certificate is only there in a query that is not run as an update */
+fn certificate() -> Result<Vec<u8>, Error> {
+    ic_cdk::api::data_certificate().ok_or(Error::InvalidState {
+        current: String::from("update call"),
+        action: String::from("certify the response, call it as a query"),
+    })
+}
+
+fn encode_witness(tree: HashTree) -> Vec<u8> {
+    HashTree::Labeled(JOBS_LABEL.to_vec(), Box::new(tree)).to_cbor()
+}
+
+#[ic_cdk::query]
+fn fetch_job_certified(job_id: u64) -> Result<CertifiedJob, Error> {
+    let job = get_job(job_id)?;
+    let certificate = certificate()?;
+    let witness = TREE.with(|tree| encode_witness(tree.borrow().witness(job_id, job_id)));
+
+    Ok(CertifiedJob { job_bytes: job_bytes(&job), job, certificate, witness })
+}
+
+/* list_jobs with a witness. the witness covers the whole range of ids the page spans, so a client
+   can also see which jobs in that range were left out */
+#[ic_cdk::query]
+fn list_jobs_certified(request: ListJobs) -> Result<CertifiedJobPage, Error> {
+    let page = list_jobs(request);
+    let certificate = certificate()?;
+
+    let ids = page.jobs.iter().map(|job| job.id);
+    let witness = TREE.with(|tree| {
+        let tree = tree.borrow();
+        match (ids.clone().min(), ids.max()) {
+            (Some(first), Some(last)) => encode_witness(tree.witness(first, last)),
+            _ => encode_witness(HashTree::Pruned(tree.root_hash())),
+        }
+    });
+
+    let job_bytes = page.jobs.iter().map(job_bytes).collect();
+    Ok(CertifiedJobPage { page, job_bytes, certificate, witness })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_job;
+
+    fn store(job: &Job) {
+        STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+    }
+
+    /* what a client does with a certified job: the witness has to lead to the certified root, and its leaf for
+       the job has to be the hash of the returned bytes, which decode to the job */
+    fn verify(job_id: u64, bytes: &[u8], witness: &HashTree) -> Job {
+        let root = TREE.with(|tree| tree.borrow().root_hash());
+        assert_eq!(witness.digest(), root);
+        let leaf: Hash = Sha256::digest(bytes).into();
+        assert_eq!(witness.lookup(&crate::hash_tree::job_key(job_id)), Some(leaf.as_slice()));
+        candid::decode_one(bytes).unwrap()
+    }
+
+    #[test]
+    fn a_witnessed_leaf_is_the_hash_of_the_returned_bytes() {
+        let mut job = sample_job(2, "Rust developer");
+        job.skills = vec![String::from("rust")];
+        store(&sample_job(1, "Solidity auditor"));
+        store(&job);
+        rebuild();
+
+        let witness = TREE.with(|tree| tree.borrow().witness(2, 2));
+        let certified = verify(2, &job_bytes(&job), &witness);
+        assert_eq!(certified.title, "Rust developer");
+        assert_eq!(certified.skills, vec![String::from("rust")]);
+    }
+
+    #[test]
+    fn a_saved_job_is_certified_as_it_is_now() {
+        let mut job = sample_job(1, "Rust developer");
+        store(&job);
+        rebuild();
+
+        job.title = String::from("Senior Rust developer");
+        store(&job);
+        certify(&job);
+
+        let witness = TREE.with(|tree| tree.borrow().witness(1, 1));
+        assert_eq!(verify(1, &job_bytes(&job), &witness).title, "Senior Rust developer");
+
+        let stale: Hash = Sha256::digest(job_bytes(&sample_job(1, "Rust developer"))).into();
+        assert_ne!(witness.lookup(&crate::hash_tree::job_key(1)), Some(stale.as_slice()));
+    }
+
+    #[test]
+    fn a_page_witness_covers_every_job_on_it() {
+        let jobs: Vec<Job> = (1..=4).map(|id| sample_job(id, "Rust developer")).collect();
+        for job in &jobs {
+            store(job);
+        }
+        rebuild();
+
+        let witness = TREE.with(|tree| tree.borrow().witness(2, 4));
+        for job in &jobs[1..] {
+            assert_eq!(verify(job.id, &job_bytes(job), &witness).id, job.id);
+        }
+        assert_eq!(witness.lookup(&crate::hash_tree::job_key(1)), None);
+    }
+}
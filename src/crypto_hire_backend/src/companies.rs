@@ -0,0 +1,286 @@
+/* companies that post jobs. a company is run by one or more owners, any of whom can post jobs on its behalf */
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+
+use crate::audit::{self, Operation};
+use crate::config::ensure_moderator;
+use crate::{migrations, validate_text, validate_url, Error, IdCell, Memory, MEMORY_MANAGER};
+use crate::rate_limit::throttle;
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_COMPANY_DESCRIPTION_LEN: usize = 5_000;
+const MAX_WEBSITE_LEN: usize = 200;
+const MAX_OWNERS: usize = 10;
+
+/* a logo is referenced by the hex encoded sha-256 hash of the image */
+const LOGO_HASH_LEN: usize = 64;
+
+//the schema version new companies are written with, see migrations.rs
+const COMPANY_VERSION: u8 = 1;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Company {
+    id: u64,
+    name: String,
+    description: String,
+    website: Option<String>,
+    logo_hash: Option<String>,
+    verified: bool,          // set by a moderator once the company has been checked
+    owners: Vec<Principal>,  // may update the company and post jobs for it
+    created_at: u64,
+}
+
+//what a job shows about the company that posted it
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CompanySummary {
+    id: u64,
+    pub(crate) name: String,
+    pub(crate) website: Option<String>,
+    logo_hash: Option<String>,
+    verified: bool,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CompanyDetails {
+    name: String,
+    description: String,
+    website: Option<String>,
+    logo_hash: Option<String>,
+}
+
+impl Company {
+    pub(crate) fn summary(&self) -> CompanySummary {
+        CompanySummary {
+            id: self.id,
+            name: self.name.clone(),
+            website: self.website.clone(),
+            logo_hash: self.logo_hash.clone(),
+            verified: self.verified,
+        }
+    }
+}
+
+impl Storable for Company {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(COMPANY_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Company", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static COMPANIES: RefCell<StableBTreeMap<u64, Company, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+        ));
+
+    static COMPANY_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))), 0).expect("cannot create company counter")
+    );
+}
+
+fn company_not_found(company_id: u64) -> Error {
+    Error::NotFound { resource: String::from("company"), id: company_id.to_string() }
+}
+
+pub(crate) fn get_company(company_id: u64) -> Result<Company, Error> {
+    COMPANIES
+        .with(|companies| companies.borrow().get(&company_id))
+        .ok_or(company_not_found(company_id))
+}
+
+fn save_company(company: &Company) {
+    COMPANIES.with(|companies| companies.borrow_mut().insert(company.id, company.clone()));
+}
+
+pub(crate) fn is_owner(company: &Company, principal: &Principal) -> bool {
+    company.owners.contains(principal)
+}
+
+pub(crate) fn ensure_owner(company: &Company, action: &str) -> Result<(), Error> {
+    if is_owner(company, &caller()) {
+        Ok(())
+    } else {
+        Err(Error::unauthorized(action))
+    }
+}
+
+fn validate_details(details: &CompanyDetails) -> Result<(), Error> {
+    validate_text("name", &details.name, true, MAX_NAME_LEN)?;
+    validate_text("description", &details.description, false, MAX_COMPANY_DESCRIPTION_LEN)?;
+
+    if let Some(website) = &details.website {
+        validate_url("website", website, MAX_WEBSITE_LEN)?;
+    }
+    if let Some(logo_hash) = &details.logo_hash {
+        if logo_hash.len() != LOGO_HASH_LEN || !logo_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::validation("logo_hash", "must be a hex encoded sha-256 hash"));
+        }
+    }
+    Ok(())
+}
+
+/* creates a company owned by the caller. companies start unverified */
+#[ic_cdk::update(guard = "throttle")]
+fn create_company(details: CompanyDetails) -> Result<Company, Error> {
+    validate_details(&details)?;
+
+    let id = COMPANY_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).expect("Cannot increment company id counter");
+        current_value + 1
+    });
+
+    let company = Company {
+        id,
+        name: details.name,
+        description: details.description,
+        website: details.website,
+        logo_hash: details.logo_hash.map(|hash| hash.to_lowercase()),
+        verified: false,
+        owners: vec![caller()],
+        created_at: time(),
+    };
+    save_company(&company);
+    audit::record(Operation::CompanyCreated, None, Some(id), None);
+    Ok(company)
+}
+
+/* replaces the details of a company. a verified company that changes its name or website has to be verified again */
+#[ic_cdk::update(guard = "throttle")]
+fn update_company(company_id: u64, details: CompanyDetails) -> Result<Company, Error> {
+    let mut company = get_company(company_id)?;
+    ensure_owner(&company, "update the company")?;
+    validate_details(&details)?;
+
+    if company.name != details.name || company.website != details.website {
+        company.verified = false;
+    }
+    company.name = details.name;
+    company.description = details.description;
+    company.website = details.website;
+    company.logo_hash = details.logo_hash.map(|hash| hash.to_lowercase());
+    save_company(&company);
+    audit::record(Operation::CompanyUpdated, None, Some(company_id), None);
+    Ok(company)
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn add_company_owner(company_id: u64, owner: Principal) -> Result<(), Error> {
+    let mut company = get_company(company_id)?;
+    ensure_owner(&company, "add an owner")?;
+
+    if company.owners.contains(&owner) {
+        return Ok(());
+    }
+    if company.owners.len() >= MAX_OWNERS {
+        return Err(Error::CapacityExceeded { resource: String::from("owners"), limit: MAX_OWNERS as u64 });
+    }
+    company.owners.push(owner);
+    save_company(&company);
+    audit::record(Operation::CompanyOwnerAdded, None, Some(company_id), Some(owner));
+    Ok(())
+}
+
+/* removes an owner, a company always keeps at least one */
+#[ic_cdk::update(guard = "throttle")]
+fn remove_company_owner(company_id: u64, owner: Principal) -> Result<(), Error> {
+    let mut company = get_company(company_id)?;
+    ensure_owner(&company, "remove an owner")?;
+
+    if !company.owners.contains(&owner) {
+        return Err(Error::NotFound { resource: String::from("owner"), id: owner.to_text() });
+    }
+    if company.owners.len() == 1 {
+        return Err(Error::validation("owner", "a company must keep at least one owner"));
+    }
+    company.owners.retain(|o| *o != owner);
+    save_company(&company);
+    audit::record(Operation::CompanyOwnerRemoved, None, Some(company_id), Some(owner));
+    Ok(())
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn set_company_verified(company_id: u64, verified: bool) -> Result<(), Error> {
+    ensure_moderator("verify a company")?;
+    let mut company = get_company(company_id)?;
+
+    company.verified = verified;
+    save_company(&company);
+    audit::record(Operation::CompanyVerified, None, Some(company_id), None);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn fetch_company(company_id: u64) -> Result<Company, Error> {
+    get_company(company_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::is_employer;
+    use crate::tests::sample_job;
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n; 29])
+    }
+
+    fn company(id: u64, owners: Vec<Principal>) -> Company {
+        Company {
+            id,
+            name: String::from("Azienda"),
+            description: String::new(),
+            website: None,
+            logo_hash: None,
+            verified: false,
+            owners,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn every_owner_manages_the_company_jobs() {
+        save_company(&company(1, vec![principal(1), principal(2)]));
+        let mut job = sample_job(1, "Rust developer");
+        job.employer = principal(1);
+        job.company = Some(1);
+
+        assert!(is_employer(&job, &principal(1)));
+        assert!(is_employer(&job, &principal(2)));
+        assert!(!is_employer(&job, &principal(3)));
+    }
+
+    #[test]
+    fn a_removed_owner_loses_the_company_jobs() {
+        save_company(&company(1, vec![principal(2)]));
+        let mut job = sample_job(1, "Rust developer");
+        job.employer = principal(1);
+        job.company = Some(1);
+
+        assert!(!is_employer(&job, &principal(1)));
+        assert!(is_employer(&job, &principal(2)));
+    }
+
+    #[test]
+    fn a_job_without_a_company_belongs_to_its_creator() {
+        let mut job = sample_job(1, "Rust developer");
+        job.employer = principal(1);
+
+        assert!(is_employer(&job, &principal(1)));
+        assert!(!is_employer(&job, &principal(2)));
+    }
+}
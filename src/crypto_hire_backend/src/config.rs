@@ -0,0 +1,298 @@
+/* canister settings and the roles that manage them.
+
+   admins change the settings, the ledger, the rate limits and the arbiters, and add or remove other admins and
+   moderators. moderators verify companies and read the audit log. controllers are always admins, and admins
+   can do everything moderators can. the first admins and settings can be passed as the init or upgrade argument */
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::thread::LocalKey;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, is_controller};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+
+use crate::audit::{self, Operation};
+use crate::rate_limit::throttle;
+use crate::{
+    migrations, Error, Memory, LEDGER, MAX_APPLICANTS_PER_JOB, MAX_COVER_LETTER_LEN, MAX_DESCRIPTION_LEN,
+    MAX_TITLE_LEN, MEMORY_MANAGER,
+};
+
+//the schema version the settings are written with, see migrations.rs
+const SETTINGS_VERSION: u8 = 1;
+
+/* the most admins and moderators each */
+const MAX_ROLE_MEMBERS: u64 = 50;
+
+//what the canister charges, in the smallest unit of the bounty's ledger
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct FeeSettings {
+    posting_fee: u128, // pulled from the employer with the bounty when a job is published, and kept if it is cancelled
+}
+
+//limits on what users can submit. they can be lowered below the built-in maximums but never raised above them
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SizeLimits {
+    pub(crate) max_title_len: u32,
+    pub(crate) max_description_len: u32,
+    pub(crate) max_cover_letter_len: u32,
+    pub(crate) max_applications_per_job: u32,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        SizeLimits {
+            max_title_len: MAX_TITLE_LEN as u32,
+            max_description_len: MAX_DESCRIPTION_LEN as u32,
+            max_cover_letter_len: MAX_COVER_LETTER_LEN as u32,
+            max_applications_per_job: MAX_APPLICANTS_PER_JOB as u32,
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+struct Settings {
+    fees: FeeSettings,
+    limits: SizeLimits,
+}
+
+impl Storable for Settings {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(SETTINGS_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Settings", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+//the argument to install and upgrade the canister with, fields left out are kept as they are
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct InitArgs {
+    admins: Option<Vec<Principal>>,     // replaces the admins
+    moderators: Option<Vec<Principal>>, // replaces the moderators
+    config: Option<ConfigUpdate>,
+}
+
+//the changes set_config makes, fields left out are kept as they are
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ConfigUpdate {
+    ledger: Option<Principal>, // the ICRC-1 ledger new bounties are paid in
+    fees: Option<FeeSettings>,
+    limits: Option<SizeLimits>,
+}
+
+//everything an admin can see about the canister's configuration
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CanisterConfig {
+    admins: Vec<Principal>,
+    moderators: Vec<Principal>,
+    ledger: Option<Principal>,
+    fees: FeeSettings,
+    limits: SizeLimits,
+}
+
+//the principals holding a role
+type Members = RefCell<StableBTreeMap<Principal, (), Memory>>;
+
+thread_local! {
+    static SETTINGS: RefCell<Cell<Settings, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))), Settings::default())
+            .expect("cannot create settings")
+    );
+
+    static ADMINS: Members = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+        ));
+
+    static MODERATORS: Members = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+        ));
+}
+
+pub(crate) fn size_limits() -> SizeLimits {
+    SETTINGS.with(|settings| settings.borrow().get().limits.clone())
+}
+
+pub(crate) fn posting_fee() -> u128 {
+    SETTINGS.with(|settings| settings.borrow().get().fees.posting_fee)
+}
+
+fn is_admin(principal: &Principal) -> bool {
+    is_controller(principal) || ADMINS.with(|admins| admins.borrow().contains_key(principal))
+}
+
+pub(crate) fn ensure_admin(action: &str) -> Result<(), Error> {
+    if is_admin(&caller()) {
+        Ok(())
+    } else {
+        Err(Error::unauthorized(action))
+    }
+}
+
+pub(crate) fn ensure_moderator(action: &str) -> Result<(), Error> {
+    let principal = caller();
+    if is_admin(&principal) || MODERATORS.with(|moderators| moderators.borrow().contains_key(&principal)) {
+        Ok(())
+    } else {
+        Err(Error::unauthorized(action))
+    }
+}
+
+fn validate_limits(limits: &SizeLimits) -> Result<(), Error> {
+    let maximums = SizeLimits::default();
+    for (field, value, max) in [
+        ("max_title_len", limits.max_title_len, maximums.max_title_len),
+        ("max_description_len", limits.max_description_len, maximums.max_description_len),
+        ("max_cover_letter_len", limits.max_cover_letter_len, maximums.max_cover_letter_len),
+        ("max_applications_per_job", limits.max_applications_per_job, maximums.max_applications_per_job),
+    ] {
+        if !(1..=max).contains(&value) {
+            return Err(Error::validation(field, &format!("must be between 1 and {}", max)));
+        }
+    }
+    Ok(())
+}
+
+fn apply_update(update: ConfigUpdate) -> Result<(), Error> {
+    if let Some(limits) = &update.limits {
+        validate_limits(limits)?;
+    }
+
+    if let Some(ledger) = update.ledger {
+        LEDGER
+            .with(|cell| cell.borrow_mut().set(Some(ledger)))
+            .expect("cannot set ledger");
+    }
+    let mut settings = SETTINGS.with(|settings| settings.borrow().get().clone());
+    if let Some(fees) = update.fees {
+        settings.fees = fees;
+    }
+    if let Some(limits) = update.limits {
+        settings.limits = limits;
+    }
+    SETTINGS
+        .with(|cell| cell.borrow_mut().set(settings))
+        .expect("cannot set settings");
+    Ok(())
+}
+
+fn replace_members(role: &'static LocalKey<Members>, members: Vec<Principal>) -> Result<(), Error> {
+    if members.len() as u64 > MAX_ROLE_MEMBERS {
+        return Err(Error::CapacityExceeded { resource: String::from("role members"), limit: MAX_ROLE_MEMBERS });
+    }
+    role.with(|role| {
+        let mut role = role.borrow_mut();
+        let current: Vec<Principal> = role.iter().map(|(member, _)| member).collect();
+        for member in current {
+            role.remove(&member);
+        }
+        for member in members {
+            role.insert(member, ());
+        }
+    });
+    Ok(())
+}
+
+fn apply_args(args: InitArgs) -> Result<(), Error> {
+    if let Some(admins) = args.admins {
+        replace_members(&ADMINS, admins)?;
+    }
+    if let Some(moderators) = args.moderators {
+        replace_members(&MODERATORS, moderators)?;
+    }
+    match args.config {
+        Some(update) => apply_update(update),
+        None => Ok(()),
+    }
+}
+
+/* applies the init or upgrade argument. a bad argument traps, so an install or upgrade with it fails */
+pub(crate) fn apply_init_args(args: Option<InitArgs>) {
+    let Some(args) = args else {
+        return;
+    };
+    if let Err(error) = apply_args(args) {
+        ic_cdk::trap(&format!("invalid init argument: {:?}", error));
+    }
+    audit::record(Operation::ConfigChanged, None, None, None);
+}
+
+fn members(role: &'static LocalKey<Members>) -> Vec<Principal> {
+    role.with(|role| role.borrow().iter().map(|(member, _)| member).collect())
+}
+
+fn add_member(role: &'static LocalKey<Members>, member: Principal) -> Result<(), Error> {
+    role.with(|role| {
+        let mut role = role.borrow_mut();
+        if !role.contains_key(&member) && role.len() >= MAX_ROLE_MEMBERS {
+            return Err(Error::CapacityExceeded { resource: String::from("role members"), limit: MAX_ROLE_MEMBERS });
+        }
+        role.insert(member, ());
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_config() -> Result<CanisterConfig, Error> {
+    ensure_admin("read the configuration")?;
+    let settings = SETTINGS.with(|settings| settings.borrow().get().clone());
+
+    Ok(CanisterConfig {
+        admins: members(&ADMINS),
+        moderators: members(&MODERATORS),
+        ledger: LEDGER.with(|cell| *cell.borrow().get()),
+        fees: settings.fees,
+        limits: settings.limits,
+    })
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn set_config(update: ConfigUpdate) -> Result<(), Error> {
+    ensure_admin("change the configuration")?;
+    apply_update(update)?;
+    audit::record(Operation::ConfigChanged, None, None, None);
+    Ok(())
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn add_admin(admin: Principal) -> Result<(), Error> {
+    ensure_admin("add an admin")?;
+    add_member(&ADMINS, admin)?;
+    audit::record(Operation::AdminAdded, None, None, Some(admin));
+    Ok(())
+}
+
+/* controllers stay admins whatever is removed here, so the canister can never be left without one */
+#[ic_cdk::update(guard = "throttle")]
+fn remove_admin(admin: Principal) -> Result<(), Error> {
+    ensure_admin("remove an admin")?;
+    ADMINS.with(|admins| admins.borrow_mut().remove(&admin));
+    audit::record(Operation::AdminRemoved, None, None, Some(admin));
+    Ok(())
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn add_moderator(moderator: Principal) -> Result<(), Error> {
+    ensure_admin("add a moderator")?;
+    add_member(&MODERATORS, moderator)?;
+    audit::record(Operation::ModeratorAdded, None, None, Some(moderator));
+    Ok(())
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn remove_moderator(moderator: Principal) -> Result<(), Error> {
+    ensure_admin("remove a moderator")?;
+    MODERATORS.with(|moderators| moderators.borrow_mut().remove(&moderator));
+    audit::record(Operation::ModeratorRemoved, None, None, Some(moderator));
+    Ok(())
+}
@@ -0,0 +1,485 @@
+/* disputes between the employer and the hired applicant of a job, ruled on by a panel of arbiters.
+   while a dispute is open the job's escrow is frozen, the ruling decides where it goes */
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::borrow::Cow;
+
+use crate::audit::{self, Operation};
+use crate::{
+    get_job, ledger, lock_escrow, migrations, milestones, save_job, settle_escrow, validate_text, Error, EscrowStatus, IdCell,
+    Job, JobStatus, Memory, MEMORY_MANAGER,
+};
+use crate::config::ensure_admin;
+use crate::rate_limit::throttle;
+
+const MAX_REASON_LEN: usize = 2_000;
+const MAX_EVIDENCE_LEN: usize = 2_000;
+const MAX_EVIDENCE_PER_DISPUTE: usize = 20;
+
+/* how long a panel has to reach a majority before an admin may rule in its place */
+const DEADLOCK_TIMEOUT_SECS: u64 = 30 * 24 * 60 * 60;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+//the schema version new disputes are written with, see migrations.rs
+const DISPUTE_VERSION: u8 = 1;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Dispute {
+    id: u64,
+    job_id: u64,
+    employer: Principal,
+    worker: Principal,       // the hired applicant
+    opened_by: Principal,
+    opened_at: u64,
+    reason: String,
+    panel: Vec<Principal>,   // the arbiters when the dispute was opened, a majority of them decides
+    evidence: Vec<Evidence>,
+    votes: Vec<Vote>,
+    status: DisputeStatus,
+    ruling: Option<Ruling>,
+    resolved_at: Option<u64>,
+    worker_payout: u128,     // what the ruling pays each side, fixed when it is reached
+    employer_payout: u128,
+    worker_paid: bool,       // the payouts that have gone through
+    employer_paid: bool,
+}
+
+//text, or the hash of a document kept elsewhere, handed in by either party
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Evidence {
+    submitted_by: Principal,
+    content: String,
+    submitted_at: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Vote {
+    arbiter: Principal,
+    ruling: Ruling,
+    cast_at: u64,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum DisputeStatus {
+    Open,     // waiting for evidence and votes
+    Resolved, // a ruling was reached, see worker_paid and employer_paid for the payouts
+    Settled,  // the ruling has been paid out and the job closed
+}
+
+//what happens to the part of the escrow that has not been paid out yet
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum Ruling {
+    ReleaseToWorker,
+    RefundEmployer,
+    Split { worker_amount: u128 }, // the rest goes back to the employer
+}
+
+impl Storable for Dispute {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(DISPUTE_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Dispute", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    /*the principals that may rule on disputes, managed by an admin*/
+    static ARBITERS: RefCell<StableBTreeMap<Principal, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+        ));
+
+    static DISPUTES: RefCell<StableBTreeMap<u64, Dispute, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+        ));
+
+    static DISPUTE_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))), 0).expect("cannot create dispute counter")
+    );
+}
+
+fn dispute_not_found(dispute_id: u64) -> Error {
+    Error::NotFound { resource: String::from("dispute"), id: dispute_id.to_string() }
+}
+
+fn get_dispute(dispute_id: u64) -> Result<Dispute, Error> {
+    DISPUTES
+        .with(|disputes| disputes.borrow().get(&dispute_id))
+        .ok_or(dispute_not_found(dispute_id))
+}
+
+fn save_dispute(dispute: &Dispute) {
+    DISPUTES.with(|disputes| disputes.borrow_mut().insert(dispute.id, dispute.clone()));
+}
+
+fn ensure_dispute_status(dispute: &Dispute, status: DisputeStatus, action: &str) -> Result<(), Error> {
+    if dispute.status == status {
+        Ok(())
+    } else {
+        Err(Error::InvalidState {
+            current: format!("dispute {:?}", dispute.status),
+            action: action.to_string(),
+        })
+    }
+}
+
+fn is_party(dispute: &Dispute, principal: Principal) -> bool {
+    dispute.employer == principal || dispute.worker == principal
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn add_arbiter(arbiter: Principal) -> Result<(), Error> {
+    ensure_admin("add an arbiter")?;
+    ARBITERS.with(|arbiters| arbiters.borrow_mut().insert(arbiter, ()));
+    audit::record(Operation::ArbiterAdded, None, None, Some(arbiter));
+    Ok(())
+}
+
+/* a removed arbiter keeps its seat on the panels of disputes that are already open */
+#[ic_cdk::update(guard = "throttle")]
+fn remove_arbiter(arbiter: Principal) -> Result<(), Error> {
+    ensure_admin("remove an arbiter")?;
+    ARBITERS.with(|arbiters| arbiters.borrow_mut().remove(&arbiter));
+    audit::record(Operation::ArbiterRemoved, None, None, Some(arbiter));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_arbiters() -> Vec<Principal> {
+    ARBITERS.with(|arbiters| arbiters.borrow().iter().map(|(arbiter, _)| arbiter).collect())
+}
+
+/* either party of a filled job with a bounty in escrow can open a dispute, which freezes the escrow */
+#[ic_cdk::update(guard = "throttle")]
+fn open_dispute(job_id: u64, reason: String) -> Result<Dispute, Error> {
+    let mut job = get_job(job_id)?;
+    let opened_by = caller();
+    let worker = job.worker().ok_or(Error::InvalidState {
+        current: format!("{:?}", job.status),
+        action: String::from("open a dispute"),
+    })?;
+    if opened_by != job.employer && opened_by != worker {
+        return Err(Error::unauthorized("open a dispute"));
+    }
+    job.ensure_status(&[JobStatus::Filled], "open a dispute")?;
+    job.ensure_escrow_idle()?;
+    if job.escrow.as_ref().map(|e| e.status) != Some(EscrowStatus::Held) {
+        return Err(Error::InvalidState {
+            current: String::from("escrow not held"),
+            action: String::from("open a dispute"),
+        });
+    }
+    validate_text("reason", &reason, true, MAX_REASON_LEN)?;
+
+    let panel = list_arbiters();
+    if panel.is_empty() {
+        return Err(Error::InvalidState {
+            current: String::from("no arbiters configured"),
+            action: String::from("open a dispute"),
+        });
+    }
+
+    let id = DISPUTE_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).expect("Cannot increment dispute id counter");
+        current_value + 1
+    });
+
+    let dispute = Dispute {
+        id,
+        job_id,
+        employer: job.employer,
+        worker,
+        opened_by,
+        opened_at: time(),
+        reason,
+        panel,
+        evidence: vec![],
+        votes: vec![],
+        status: DisputeStatus::Open,
+        ruling: None,
+        resolved_at: None,
+        worker_payout: 0,
+        employer_payout: 0,
+        worker_paid: false,
+        employer_paid: false,
+    };
+    save_dispute(&dispute);
+
+    job.dispute = Some(id);
+    job.disputes.push(id);
+    save_job(&job);
+    audit::record(Operation::DisputeOpened, Some(job_id), Some(id), None);
+
+    Ok(dispute)
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn submit_evidence(dispute_id: u64, content: String) -> Result<(), Error> {
+    let mut dispute = get_dispute(dispute_id)?;
+    if !is_party(&dispute, caller()) {
+        return Err(Error::unauthorized("submit evidence"));
+    }
+    ensure_dispute_status(&dispute, DisputeStatus::Open, "submit evidence")?;
+    validate_text("content", &content, true, MAX_EVIDENCE_LEN)?;
+    if dispute.evidence.len() >= MAX_EVIDENCE_PER_DISPUTE {
+        return Err(Error::CapacityExceeded {
+            resource: String::from("evidence"),
+            limit: MAX_EVIDENCE_PER_DISPUTE as u64,
+        });
+    }
+
+    dispute.evidence.push(Evidence { submitted_by: caller(), content, submitted_at: time() });
+    save_dispute(&dispute);
+    audit::record(Operation::EvidenceSubmitted, Some(dispute.job_id), Some(dispute_id), None);
+    Ok(())
+}
+
+/* an arbiter on the panel casts, or changes, their vote. once a majority of the panel agrees on
+   the same ruling the dispute is resolved and the ruling is paid out */
+#[ic_cdk::update(guard = "throttle")]
+async fn rule_on_dispute(dispute_id: u64, ruling: Ruling) -> Result<Dispute, Error> {
+    let mut dispute = get_dispute(dispute_id)?;
+    let arbiter = caller();
+    if !dispute.panel.contains(&arbiter) {
+        return Err(Error::unauthorized("rule on the dispute"));
+    }
+    ensure_dispute_status(&dispute, DisputeStatus::Open, "rule on the dispute")?;
+
+    let (worker_payout, employer_payout) = payouts(ruling, remaining_escrow(&get_job(dispute.job_id)?))?;
+
+    dispute.votes.retain(|vote| vote.arbiter != arbiter);
+    dispute.votes.push(Vote { arbiter, ruling, cast_at: time() });
+
+    if agreeing(&dispute, ruling) >= majority(&dispute) {
+        resolve(&mut dispute, ruling, worker_payout, employer_payout);
+    }
+    save_dispute(&dispute);
+    audit::record(Operation::DisputeVoteCast, Some(dispute.job_id), Some(dispute_id), None);
+
+    if dispute.status == DisputeStatus::Resolved {
+        execute_ruling(dispute_id).await
+    } else {
+        Ok(dispute)
+    }
+}
+
+/* an admin rules on a dispute whose panel cannot agree: every arbiter has voted without a majority for
+   any ruling, or no majority was reached within DEADLOCK_TIMEOUT_SECS of the dispute being opened */
+#[ic_cdk::update(guard = "throttle")]
+async fn break_deadlock(dispute_id: u64, ruling: Ruling) -> Result<Dispute, Error> {
+    ensure_admin("break a deadlocked dispute")?;
+    let mut dispute = get_dispute(dispute_id)?;
+    ensure_dispute_status(&dispute, DisputeStatus::Open, "break the deadlock")?;
+    if !is_deadlocked(&dispute, time()) {
+        return Err(Error::InvalidState {
+            current: String::from("panel still deciding"),
+            action: String::from("break the deadlock"),
+        });
+    }
+
+    let (worker_payout, employer_payout) = payouts(ruling, remaining_escrow(&get_job(dispute.job_id)?))?;
+    resolve(&mut dispute, ruling, worker_payout, employer_payout);
+    save_dispute(&dispute);
+    audit::record(Operation::DisputeDeadlockBroken, Some(dispute.job_id), Some(dispute_id), None);
+
+    execute_ruling(dispute_id).await
+}
+
+/* pays out a resolved dispute. runs as soon as the ruling is reached, and can be called again by a
+   party or an arbiter if a payout failed */
+#[ic_cdk::update(guard = "throttle")]
+async fn execute_ruling(dispute_id: u64) -> Result<Dispute, Error> {
+    let dispute = get_dispute(dispute_id)?;
+    let who = caller();
+    if !is_party(&dispute, who) && !dispute.panel.contains(&who) && ensure_admin("execute the ruling").is_err() {
+        return Err(Error::unauthorized("execute the ruling"));
+    }
+    ensure_dispute_status(&dispute, DisputeStatus::Resolved, "execute the ruling")?;
+
+    if !dispute.worker_paid {
+        pay(dispute_id, dispute.worker, dispute.worker_payout, true).await?;
+    }
+    if !dispute.employer_paid {
+        pay(dispute_id, dispute.employer, dispute.employer_payout, false).await?;
+    }
+
+    let mut job = get_job(dispute.job_id)?;
+    if let Some(escrow) = job.escrow.as_mut() {
+        escrow.status = if dispute.worker_payout > 0 { EscrowStatus::Released } else { EscrowStatus::Refunded };
+    }
+    milestones::release_all(&mut job);
+    job.dispute = None;
+    job.transition(JobStatus::Closed)?;
+    save_job(&job);
+
+    let mut dispute = get_dispute(dispute_id)?;
+    dispute.status = DisputeStatus::Settled;
+    save_dispute(&dispute);
+    audit::record(Operation::DisputeSettled, Some(dispute.job_id), Some(dispute_id), None);
+    Ok(dispute)
+}
+
+/* what a ruling pays the worker and the employer out of what is left in escrow */
+fn payouts(ruling: Ruling, remaining: u128) -> Result<(u128, u128), Error> {
+    match ruling {
+        Ruling::ReleaseToWorker => Ok((remaining, 0)),
+        Ruling::RefundEmployer => Ok((0, remaining)),
+        Ruling::Split { worker_amount } if worker_amount <= remaining => Ok((worker_amount, remaining - worker_amount)),
+        Ruling::Split { .. } => Err(Error::validation("worker_amount", &format!("only {} is left in escrow", remaining))),
+    }
+}
+
+fn majority(dispute: &Dispute) -> usize {
+    dispute.panel.len() / 2 + 1
+}
+
+fn agreeing(dispute: &Dispute, ruling: Ruling) -> usize {
+    dispute.votes.iter().filter(|vote| vote.ruling == ruling).count()
+}
+
+/* an open dispute the panel will not settle on its own */
+fn is_deadlocked(dispute: &Dispute, now: u64) -> bool {
+    let everyone_voted = dispute.panel.iter().all(|arbiter| dispute.votes.iter().any(|vote| vote.arbiter == *arbiter));
+    let timed_out = now >= dispute.opened_at.saturating_add(DEADLOCK_TIMEOUT_SECS * NANOS_PER_SEC);
+    everyone_voted || timed_out
+}
+
+fn resolve(dispute: &mut Dispute, ruling: Ruling, worker_payout: u128, employer_payout: u128) {
+    dispute.status = DisputeStatus::Resolved;
+    dispute.ruling = Some(ruling);
+    dispute.resolved_at = Some(time());
+    dispute.worker_payout = worker_payout;
+    dispute.employer_payout = employer_payout;
+}
+
+/* what is left of the escrow once milestone payouts are taken off */
+fn remaining_escrow(job: &Job) -> u128 {
+    job.escrow.as_ref().map_or(0, |e| e.amount.saturating_sub(e.released))
+}
+
+/* one payout of a ruling. the escrow is locked for the transfer and the dispute remembers the payout
+   once it went through, so a retry never pays twice. a payout the ledger fee would eat is not sent */
+async fn pay(dispute_id: u64, to: Principal, amount: u128, to_worker: bool) -> Result<(), Error> {
+    let mut dispute = get_dispute(dispute_id)?;
+    if amount > 0 {
+        let mut job = get_job(dispute.job_id)?;
+        if job.escrow.as_ref().map(|e| e.status) != Some(EscrowStatus::Held) {
+            return Err(Error::InvalidState {
+                current: String::from("escrow not held"),
+                action: String::from("pay out the ruling"),
+            });
+        }
+
+        let (ledger_id, _) = lock_escrow(&mut job, EscrowStatus::Releasing);
+        let result = ledger::payout(ledger_id, to, amount, job.id).await;
+        let mut job = settle_escrow(job.id, result, EscrowStatus::Held, EscrowStatus::Held)?;
+        if let Some(escrow) = job.escrow.as_mut() {
+            escrow.released = escrow.released.saturating_add(amount);
+        }
+        save_job(&job);
+        dispute = get_dispute(dispute_id)?;
+    }
+
+    if to_worker {
+        dispute.worker_paid = true;
+    } else {
+        dispute.employer_paid = true;
+    }
+    save_dispute(&dispute);
+    Ok(())
+}
+
+/* a dispute can be read by both parties and by the arbiters on its panel */
+#[ic_cdk::query]
+fn fetch_dispute(dispute_id: u64) -> Result<Dispute, Error> {
+    let dispute = get_dispute(dispute_id)?;
+    let who = caller();
+    if !is_party(&dispute, who) && !dispute.panel.contains(&who) {
+        return Err(Error::unauthorized("view the dispute"));
+    }
+    Ok(dispute)
+}
+
+/* every dispute raised on a job, for its employer and hired applicant */
+#[ic_cdk::query]
+fn list_job_disputes(job_id: u64) -> Result<Vec<Dispute>, Error> {
+    let job = get_job(job_id)?;
+    let who = caller();
+    if who != job.employer && job.worker() != Some(who) {
+        return Err(Error::unauthorized("view the job's disputes"));
+    }
+
+    job.disputes.iter().map(|id| get_dispute(*id)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dispute(panel: u8, votes: &[(u8, Ruling)]) -> Dispute {
+        let principal = |n: u8| Principal::from_slice(&[n; 29]);
+        Dispute {
+            id: 1,
+            job_id: 1,
+            employer: principal(100),
+            worker: principal(101),
+            opened_by: principal(100),
+            opened_at: 1_000,
+            reason: String::from("the work was not delivered"),
+            panel: (1..=panel).map(principal).collect(),
+            evidence: vec![],
+            votes: votes
+                .iter()
+                .map(|&(arbiter, ruling)| Vote { arbiter: principal(arbiter), ruling, cast_at: 1_000 })
+                .collect(),
+            status: DisputeStatus::Open,
+            ruling: None,
+            resolved_at: None,
+            worker_payout: 0,
+            employer_payout: 0,
+            worker_paid: false,
+            employer_paid: false,
+        }
+    }
+
+    #[test]
+    fn splits_what_is_left_in_escrow() {
+        assert_eq!(payouts(Ruling::ReleaseToWorker, 500).unwrap(), (500, 0));
+        assert_eq!(payouts(Ruling::RefundEmployer, 500).unwrap(), (0, 500));
+        assert_eq!(payouts(Ruling::Split { worker_amount: 3 }, 500).unwrap(), (3, 497));
+        assert!(payouts(Ruling::Split { worker_amount: 501 }, 500).is_err());
+    }
+
+    #[test]
+    fn a_split_panel_is_deadlocked_once_everyone_voted() {
+        let split = dispute(2, &[(1, Ruling::ReleaseToWorker), (2, Ruling::RefundEmployer)]);
+        assert!(agreeing(&split, Ruling::ReleaseToWorker) < majority(&split));
+        assert!(is_deadlocked(&split, split.opened_at));
+
+        let waiting = dispute(3, &[(1, Ruling::ReleaseToWorker), (2, Ruling::RefundEmployer)]);
+        assert!(!is_deadlocked(&waiting, waiting.opened_at));
+    }
+
+    #[test]
+    fn a_panel_that_does_not_decide_in_time_is_deadlocked() {
+        let silent = dispute(3, &[]);
+        let timeout = DEADLOCK_TIMEOUT_SECS * NANOS_PER_SEC;
+        assert!(!is_deadlocked(&silent, silent.opened_at + timeout - 1));
+        assert!(is_deadlocked(&silent, silent.opened_at + timeout));
+    }
+}
@@ -0,0 +1,242 @@
+/* application deadlines. open jobs are closed by a recurring timer once their deadline has passed */
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::time::Duration;
+
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+
+use crate::audit::{self, Operation};
+use crate::{ensure_employer, get_job, save_job, validate_deadline, Error, Job, JobStatus, Memory, MEMORY_MANAGER};
+use crate::rate_limit::throttle;
+
+/* how often the timer looks for jobs whose deadline has passed */
+const EXPIRY_CHECK_SECS: u64 = 60;
+
+/* the most deadlines one run of the timer goes through, the next run carries on after the last one */
+const MAX_EXPIRED_PER_RUN: usize = 100;
+
+thread_local! {
+    /*the deadline of every job that has one, keyed by (deadline, job_id) so the ones that have passed come first.
+      an entry is dropped once its job is closed or no longer open*/
+    static DEADLINES: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+        ));
+
+    /*the last deadline the previous run went through, None to start from the earliest. a job whose escrow is busy
+      keeps its deadline, so without this enough of them would fill every run and nothing behind them would close*/
+    static CURSOR: RefCell<Option<(u64, u64)>> = const { RefCell::new(None) };
+}
+
+/* keeps the deadline index in step with a job's deadline */
+pub(crate) fn track(job_id: u64, previous: Option<u64>, deadline: Option<u64>) {
+    DEADLINES.with(|deadlines| {
+        let mut deadlines = deadlines.borrow_mut();
+        if let Some(previous) = previous {
+            deadlines.remove(&(previous, job_id));
+        }
+        if let Some(deadline) = deadline {
+            deadlines.insert((deadline, job_id), ());
+        }
+    });
+}
+
+pub(crate) fn has_passed(deadline: Option<u64>, now: u64) -> bool {
+    deadline.is_some_and(|deadline| deadline <= now)
+}
+
+/* moves the open jobs whose deadline is at or before `now` to Closed and drops their deadlines, along with
+   those of jobs that are gone or no longer open. the closed jobs are returned for the caller to save. the time
+   is passed in rather than read here so the expiry can be driven with any clock */
+fn close_expired(now: u64) -> Vec<Job> {
+    let start = match CURSOR.with(|cursor| *cursor.borrow()) {
+        Some(last) => Bound::Excluded(last),
+        None => Bound::Unbounded,
+    };
+    let due: Vec<(u64, u64)> = DEADLINES.with(|deadlines| {
+        deadlines
+            .borrow()
+            .range((start, Bound::Included((now, u64::MAX))))
+            .take(MAX_EXPIRED_PER_RUN)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    // once the run gets to the end of what is due, the next one starts over from the earliest deadline
+    let next = if due.len() < MAX_EXPIRED_PER_RUN { None } else { due.last().copied() };
+    CURSOR.with(|cursor| *cursor.borrow_mut() = next);
+
+    let mut closed = vec![];
+    for (deadline, job_id) in due {
+        let Ok(mut job) = get_job(job_id) else {
+            track(job_id, Some(deadline), None);
+            continue;
+        };
+        if job.status != JobStatus::Open {
+            track(job_id, Some(deadline), None);
+            continue;
+        }
+        // a job whose escrow is busy is left for the next run
+        if job.transition(JobStatus::Closed).is_ok() {
+            track(job_id, Some(deadline), None);
+            closed.push(job);
+        }
+    }
+    closed
+}
+
+/* closes the open jobs whose deadline is at or before `now`. each one gets a JobExpired event */
+pub(crate) fn expire_jobs(now: u64) {
+    for job in close_expired(now) {
+        save_job(&job);
+        audit::record(Operation::JobExpired, Some(job.id), None, None);
+    }
+}
+
+/* timers do not survive an upgrade, so this runs from init and post_upgrade */
+pub(crate) fn start_expiry_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(EXPIRY_CHECK_SECS), || expire_jobs(time()));
+}
+
+/* moves a job's application deadline, or removes it with None. the new deadline must be in the future,
+   a job that was closed by its deadline can be reopened with publish_job after the deadline is extended */
+#[ic_cdk::update(guard = "throttle")]
+fn set_deadline(job_id: u64, deadline: Option<u64>) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    ensure_employer(&job, "set the deadline")?;
+    job.ensure_status(&[JobStatus::Draft, JobStatus::Open, JobStatus::Closed], "set the deadline")?;
+    validate_deadline(deadline)?;
+
+    track(job_id, job.deadline, deadline);
+    job.deadline = deadline;
+    save_job(&job);
+    audit::record(Operation::DeadlineChanged, Some(job_id), None, None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_job;
+    use crate::{Escrow, EscrowStatus, STORAGE};
+
+    const DEADLINE: u64 = 1_000_000;
+
+    fn store(job: &Job) {
+        STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+        track(job.id, None, job.deadline);
+    }
+
+    fn job_with_deadline(id: u64, deadline: u64) -> Job {
+        let mut job = sample_job(id, "Rust developer");
+        job.deadline = Some(deadline);
+        job
+    }
+
+    fn tracked() -> Vec<(u64, u64)> {
+        DEADLINES.with(|deadlines| deadlines.borrow().iter().map(|(key, _)| key).collect())
+    }
+
+    #[test]
+    fn a_deadline_passes_at_the_moment_it_is_set_for() {
+        assert!(!has_passed(Some(DEADLINE), DEADLINE - 1));
+        assert!(has_passed(Some(DEADLINE), DEADLINE));
+        assert!(!has_passed(None, u64::MAX));
+    }
+
+    #[test]
+    fn closes_an_open_job_once_its_deadline_passes() {
+        store(&job_with_deadline(1, DEADLINE));
+
+        assert!(close_expired(DEADLINE - 1).is_empty());
+        assert_eq!(tracked(), vec![(DEADLINE, 1)]);
+
+        let closed = close_expired(DEADLINE);
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].id, closed[0].status), (1, JobStatus::Closed));
+        assert!(tracked().is_empty());
+
+        assert!(close_expired(DEADLINE + 1).is_empty());
+    }
+
+    #[test]
+    fn only_closes_the_jobs_that_are_due() {
+        store(&job_with_deadline(1, DEADLINE));
+        store(&job_with_deadline(2, DEADLINE + 10));
+
+        let closed: Vec<u64> = close_expired(DEADLINE + 5).iter().map(|job| job.id).collect();
+        assert_eq!(closed, vec![1]);
+        assert_eq!(tracked(), vec![(DEADLINE + 10, 2)]);
+    }
+
+    #[test]
+    fn drops_the_deadlines_of_jobs_that_are_gone_or_not_open() {
+        let mut filled = job_with_deadline(1, DEADLINE);
+        filled.status = JobStatus::Filled;
+        store(&filled);
+        track(2, None, Some(DEADLINE));
+
+        assert!(close_expired(DEADLINE).is_empty());
+        assert!(tracked().is_empty());
+    }
+
+    #[test]
+    fn leaves_a_job_with_a_busy_escrow_for_the_next_run() {
+        let mut job = job_with_deadline(1, DEADLINE);
+        job.escrow = Some(Escrow {
+            ledger: job.employer,
+            amount: 1_000,
+            released: 0,
+            status: EscrowStatus::Releasing,
+            posting_fee: None,
+        });
+        store(&job);
+
+        assert!(close_expired(DEADLINE).is_empty());
+        assert_eq!(tracked(), vec![(DEADLINE, 1)]);
+    }
+
+    #[test]
+    fn busy_jobs_do_not_hold_up_the_jobs_behind_them() {
+        let busy = MAX_EXPIRED_PER_RUN as u64 + 5;
+        for id in 1..=busy {
+            let mut job = job_with_deadline(id, DEADLINE + id);
+            job.escrow = Some(Escrow {
+                ledger: job.employer,
+                amount: 1_000,
+                released: 0,
+                status: EscrowStatus::Funding,
+                posting_fee: None,
+            });
+            store(&job);
+        }
+        store(&job_with_deadline(busy + 1, DEADLINE + busy + 1));
+        let now = DEADLINE + busy + 1;
+
+        assert!(close_expired(now).is_empty());
+        let closed: Vec<u64> = close_expired(now).iter().map(|job| job.id).collect();
+        assert_eq!(closed, vec![busy + 1]);
+        assert_eq!(tracked().len(), busy as usize);
+
+        // the busy jobs are gone through again, from the start, once their escrow is free
+        let mut first = get_job(1).unwrap();
+        first.escrow.as_mut().unwrap().status = EscrowStatus::Unfunded;
+        STORAGE.with(|storage| storage.borrow_mut().insert(1, first));
+        let closed: Vec<u64> = close_expired(now).iter().map(|job| job.id).collect();
+        assert_eq!(closed, vec![1]);
+    }
+
+    #[test]
+    fn a_run_closes_at_most_max_expired_per_run() {
+        let total = MAX_EXPIRED_PER_RUN as u64 + 5;
+        for id in 1..=total {
+            store(&job_with_deadline(id, DEADLINE + id));
+        }
+
+        assert_eq!(close_expired(DEADLINE + total).len(), MAX_EXPIRED_PER_RUN);
+        assert_eq!(tracked().len(), 5);
+        assert_eq!(close_expired(DEADLINE + total).len(), 5);
+        assert!(tracked().is_empty());
+    }
+}
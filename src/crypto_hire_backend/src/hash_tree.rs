@@ -0,0 +1,308 @@
+/* the hash tree format of the IC interface specification, which certified data and witnesses are written in.
+   see https://internetcomputer.org/docs/current/references/ic-interface-spec#certificate
+
+   JobTree is a binary tree over job ids with the hash of every node cached, so saving a job only rehashes
+   the path from its leaf to the root. ids are dense and never reused, so leaf `id` of the tree is job `id`
+   and the tree doubles in size when an id does not fit */
+use sha2::{Digest, Sha256};
+
+pub(crate) type Hash = [u8; 32];
+
+pub(crate) enum HashTree {
+    Empty,
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(Vec<u8>, Box<HashTree>),
+    Leaf(Vec<u8>),
+    Pruned(Hash),
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn empty_hash() -> Hash {
+    domain_hash("ic-hashtree-empty", &[])
+}
+
+fn fork_hash(left: &Hash, right: &Hash) -> Hash {
+    domain_hash("ic-hashtree-fork", &[left, right])
+}
+
+pub(crate) fn labeled_hash(label: &[u8], tree: &Hash) -> Hash {
+    domain_hash("ic-hashtree-labeled", &[label, tree])
+}
+
+fn leaf_hash(value: &[u8]) -> Hash {
+    domain_hash("ic-hashtree-leaf", &[value])
+}
+
+impl HashTree {
+    /* the CBOR encoding of the tree with the self-describing tag in front, the form agents read witnesses in */
+    pub(crate) fn to_cbor(&self) -> Vec<u8> {
+        let mut bytes = vec![0xd9, 0xd9, 0xf7];
+        self.write_cbor(&mut bytes);
+        bytes
+    }
+
+    fn write_cbor(&self, bytes: &mut Vec<u8>) {
+        match self {
+            HashTree::Empty => bytes.extend([0x81, 0x00]),
+            HashTree::Fork(left, right) => {
+                bytes.extend([0x83, 0x01]);
+                left.write_cbor(bytes);
+                right.write_cbor(bytes);
+            }
+            HashTree::Labeled(label, tree) => {
+                bytes.extend([0x83, 0x02]);
+                write_cbor_bytes(bytes, label);
+                tree.write_cbor(bytes);
+            }
+            HashTree::Leaf(value) => {
+                bytes.extend([0x82, 0x03]);
+                write_cbor_bytes(bytes, value);
+            }
+            HashTree::Pruned(hash) => {
+                bytes.extend([0x82, 0x04]);
+                write_cbor_bytes(bytes, hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl HashTree {
+    /* the root hash a client computes from a witness, reconstruct in the interface specification */
+    pub(crate) fn digest(&self) -> Hash {
+        match self {
+            HashTree::Empty => empty_hash(),
+            HashTree::Fork(left, right) => fork_hash(&left.digest(), &right.digest()),
+            HashTree::Labeled(label, tree) => labeled_hash(label, &tree.digest()),
+            HashTree::Leaf(value) => leaf_hash(value),
+            HashTree::Pruned(hash) => *hash,
+        }
+    }
+
+    /* the value of the leaf under `label`, if the witness reveals it */
+    pub(crate) fn lookup(&self, label: &[u8]) -> Option<&[u8]> {
+        match self {
+            HashTree::Fork(left, right) => left.lookup(label).or_else(|| right.lookup(label)),
+            HashTree::Labeled(found, tree) if found.as_slice() == label => match tree.as_ref() {
+                HashTree::Leaf(value) => Some(value),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/* a CBOR byte string, major type 2 */
+fn write_cbor_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    let len = value.len();
+    if len < 24 {
+        bytes.push(0x40 | len as u8);
+    } else if len <= u8::MAX as usize {
+        bytes.extend([0x58, len as u8]);
+    } else if len <= u16::MAX as usize {
+        bytes.push(0x59);
+        bytes.extend((len as u16).to_be_bytes());
+    } else {
+        bytes.push(0x5a);
+        bytes.extend((len as u32).to_be_bytes());
+    }
+    bytes.extend(value);
+}
+
+/* the label of a job in the tree, 8 big-endian bytes so labels sort like ids */
+pub(crate) fn job_key(job_id: u64) -> Vec<u8> {
+    job_id.to_be_bytes().to_vec()
+}
+
+pub(crate) struct JobTree {
+    capacity: usize,            // the number of leaves, always a power of two
+    values: Vec<Option<Hash>>,  // the value of every leaf, None where there is no job
+    nodes: Vec<Hash>,           // node hashes, the root at 1 and the children of n at 2n and 2n + 1
+}
+
+impl Default for JobTree {
+    fn default() -> Self {
+        let mut tree = JobTree { capacity: 1, values: vec![None], nodes: vec![] };
+        tree.rehash_all();
+        tree
+    }
+}
+
+impl JobTree {
+    fn leaf_node_hash(&self, id: usize) -> Hash {
+        match &self.values[id] {
+            Some(value) => labeled_hash(&job_key(id as u64), &leaf_hash(value)),
+            None => empty_hash(),
+        }
+    }
+
+    fn rehash_all(&mut self) {
+        self.nodes = vec![[0; 32]; 2 * self.capacity];
+        for id in 0..self.capacity {
+            self.nodes[self.capacity + id] = self.leaf_node_hash(id);
+        }
+        for node in (1..self.capacity).rev() {
+            self.nodes[node] = fork_hash(&self.nodes[2 * node], &self.nodes[2 * node + 1]);
+        }
+    }
+
+    pub(crate) fn insert(&mut self, job_id: u64, value: Hash) {
+        let id = job_id as usize;
+        if id >= self.capacity {
+            self.capacity = (id + 1).next_power_of_two();
+            self.values.resize(self.capacity, None);
+            self.values[id] = Some(value);
+            self.rehash_all();
+            return;
+        }
+
+        self.values[id] = Some(value);
+        let mut node = self.capacity + id;
+        self.nodes[node] = self.leaf_node_hash(id);
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = fork_hash(&self.nodes[2 * node], &self.nodes[2 * node + 1]);
+        }
+    }
+
+    pub(crate) fn root_hash(&self) -> Hash {
+        self.nodes[1]
+    }
+
+    /* a witness that reveals the leaves with ids in first..=last and prunes everything else */
+    pub(crate) fn witness(&self, first: u64, last: u64) -> HashTree {
+        self.witness_node(1, 0, self.capacity, first as usize, last.min(usize::MAX as u64) as usize)
+    }
+
+    /* node covers the leaves start..end */
+    fn witness_node(&self, node: usize, start: usize, end: usize, first: usize, last: usize) -> HashTree {
+        if end <= first || start > last {
+            return HashTree::Pruned(self.nodes[node]);
+        }
+        if end - start == 1 {
+            return match &self.values[start] {
+                Some(value) => HashTree::Labeled(job_key(start as u64), Box::new(HashTree::Leaf(value.to_vec()))),
+                None => HashTree::Empty,
+            };
+        }
+
+        let middle = start + (end - start) / 2;
+        HashTree::Fork(
+            Box::new(self.witness_node(2 * node, start, middle, first, last)),
+            Box::new(self.witness_node(2 * node + 1, middle, end, first, last)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* the ids of the jobs a witness reveals */
+    fn revealed(tree: &HashTree) -> Vec<u64> {
+        match tree {
+            HashTree::Fork(left, right) => [revealed(left), revealed(right)].concat(),
+            HashTree::Labeled(label, _) => vec![u64::from_be_bytes(label.as_slice().try_into().unwrap())],
+            _ => vec![],
+        }
+    }
+
+    fn value(n: u8) -> Hash {
+        [n; 32]
+    }
+
+    fn tree_with(ids: &[u64]) -> JobTree {
+        let mut tree = JobTree::default();
+        for &id in ids {
+            tree.insert(id, value(id as u8));
+        }
+        tree
+    }
+
+    #[test]
+    fn an_empty_tree_witnesses_its_root() {
+        let tree = JobTree::default();
+        assert_eq!(tree.root_hash(), empty_hash());
+        assert_eq!(tree.witness(0, 0).digest(), tree.root_hash());
+    }
+
+    #[test]
+    fn witnesses_reconstruct_the_root() {
+        let tree = tree_with(&[1, 2, 3, 5, 8, 9, 13]);
+        for (first, last) in [(1, 1), (0, 15), (2, 5), (4, 4), (8, 13), (6, 7), (13, u64::MAX), (100, 200)] {
+            let witness = tree.witness(first, last);
+            assert_eq!(witness.digest(), tree.root_hash(), "witness({}, {})", first, last);
+        }
+    }
+
+    #[test]
+    fn witnesses_reveal_exactly_the_jobs_in_range() {
+        let tree = tree_with(&[1, 2, 3, 5, 8, 9, 13]);
+        assert_eq!(revealed(&tree.witness(2, 8)), vec![2, 3, 5, 8]);
+        assert_eq!(revealed(&tree.witness(5, 5)), vec![5]);
+        assert_eq!(revealed(&tree.witness(6, 7)), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn a_revealed_leaf_holds_the_job_value() {
+        let tree = tree_with(&[4]);
+        let witness = tree.witness(4, 4);
+        assert_eq!(witness.lookup(&job_key(4)), Some(value(4).as_slice()));
+        assert_eq!(witness.lookup(&job_key(5)), None);
+    }
+
+    #[test]
+    fn insert_grows_the_tree_to_fit_an_id() {
+        let mut tree = tree_with(&[0]);
+        assert_eq!(tree.capacity, 1);
+
+        tree.insert(5, value(5));
+        assert_eq!(tree.capacity, 8);
+        assert_eq!(tree.values.len(), 8);
+        assert_eq!(tree.nodes.len(), 16);
+
+        tree.insert(8, value(8));
+        assert_eq!(tree.capacity, 16);
+
+        // the same jobs give the same root whatever order they were inserted in
+        assert_eq!(tree.root_hash(), tree_with(&[8, 5, 0]).root_hash());
+        assert_eq!(tree.witness(0, 8).digest(), tree.root_hash());
+    }
+
+    #[test]
+    fn saving_a_job_again_changes_only_its_leaf() {
+        let mut tree = tree_with(&[1, 2, 3]);
+        let before = tree.root_hash();
+        tree.insert(2, value(42));
+        assert_ne!(tree.root_hash(), before);
+
+        let mut rebuilt = tree_with(&[1, 3]);
+        rebuilt.insert(2, value(42));
+        assert_eq!(tree.root_hash(), rebuilt.root_hash());
+        assert_eq!(tree.witness(2, 2).digest(), tree.root_hash());
+    }
+
+    #[test]
+    fn encodes_witnesses_as_tagged_cbor() {
+        assert_eq!(HashTree::Empty.to_cbor(), vec![0xd9, 0xd9, 0xf7, 0x81, 0x00]);
+
+        let tree = HashTree::Labeled(b"jobs".to_vec(), Box::new(HashTree::Leaf(vec![7])));
+        assert_eq!(tree.to_cbor(), vec![0xd9, 0xd9, 0xf7, 0x83, 0x02, 0x44, b'j', b'o', b'b', b's', 0x82, 0x03, 0x41, 7]);
+
+        let mut bytes = vec![];
+        write_cbor_bytes(&mut bytes, &[0; 32]);
+        assert_eq!(&bytes[..2], &[0x58, 32]);
+        let mut bytes = vec![];
+        write_cbor_bytes(&mut bytes, &[0; 300]);
+        assert_eq!(&bytes[..3], &[0x59, 0x01, 0x2c]);
+    }
+}
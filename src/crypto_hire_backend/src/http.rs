@@ -0,0 +1,374 @@
+/* job listings over plain HTTP, for browsers and job aggregators that do not speak candid.
+
+       GET /jobs              the newest open jobs as JSON
+       GET /jobs/{id}         one job as JSON
+       GET /jobs/{id}/jsonld  one job as a schema.org JobPosting in JSON-LD
+       GET /feed.rss          the newest open jobs as an RSS 2.0 feed
+
+   drafts are not served, they are not public yet.
+
+   the responses are not certified, so the HTTP gateways on <canister id>.icp0.io reject them. the listings are
+   served on the raw domain, <canister id>.raw.icp0.io, where the gateway passes uncertified responses through,
+   and every link in them points there */
+use candid::CandidType;
+use serde_json::{json, Value};
+
+use crate::{companies, get_job, list_jobs, Compensation, EmploymentType, Job, JobStatus, ListJobs, SortOrder, WorkLocation};
+
+/* how many jobs the JSON and RSS feeds list */
+const FEED_SIZE: u32 = 50;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn new(status_code: u16, content_type: &str, body: String) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                (String::from("Content-Type"), format!("{}; charset=utf-8", content_type)),
+                (String::from("Access-Control-Allow-Origin"), String::from("*")),
+            ],
+            body: body.into_bytes(),
+        }
+    }
+
+    fn json(value: Value) -> Self {
+        Self::new(200, "application/json", value.to_string())
+    }
+
+    fn error(status_code: u16, message: &str) -> Self {
+        Self::new(status_code, "application/json", json!({ "error": message }).to_string())
+    }
+}
+
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    respond(&request, &base_url())
+}
+
+/* a HEAD request gets the status and headers a GET would, without the body */
+fn respond(request: &HttpRequest, base_url: &str) -> HttpResponse {
+    let mut response = route(request, base_url);
+    if request.method == "HEAD" {
+        response.body.clear();
+    }
+    response
+}
+
+fn route(request: &HttpRequest, base_url: &str) -> HttpResponse {
+    if request.method != "GET" && request.method != "HEAD" {
+        return HttpResponse::error(405, "only GET is supported");
+    }
+
+    let path = request.url.split(['?', '#']).next().unwrap_or("/");
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match segments.as_slice() {
+        ["jobs"] => HttpResponse::json(Value::Array(
+            feed_jobs().iter().map(|job| job_json(job, base_url)).collect(),
+        )),
+        ["jobs", id] => match public_job(id) {
+            Ok(job) => HttpResponse::json(job_json(&job, base_url)),
+            Err(response) => response,
+        },
+        ["jobs", id, "jsonld"] => match public_job(id) {
+            Ok(job) => HttpResponse::new(200, "application/ld+json", job_posting(&job, base_url).to_string()),
+            Err(response) => response,
+        },
+        ["feed.rss"] => HttpResponse::new(200, "application/rss+xml", rss_feed(&feed_jobs(), base_url)),
+        _ => HttpResponse::error(404, "not found"),
+    }
+}
+
+/* the raw domain of the canister, links in the responses point back to it. the Host header is never used,
+   it is whatever the client sent */
+fn base_url() -> String {
+    format!("https://{}.raw.icp0.io", ic_cdk::id())
+}
+
+fn feed_jobs() -> Vec<Job> {
+    list_jobs(ListJobs {
+        limit: Some(FEED_SIZE),
+        status: Some(JobStatus::Open),
+        order: Some(SortOrder::Descending),
+        ..Default::default()
+    })
+    .jobs
+}
+
+fn public_job(id: &str) -> Result<Job, HttpResponse> {
+    let job_id = id.parse::<u64>().map_err(|_| HttpResponse::error(400, "job id must be a number"))?;
+    match get_job(job_id) {
+        Ok(job) if job.status != JobStatus::Draft => Ok(job),
+        _ => Err(HttpResponse::error(404, "job not found")),
+    }
+}
+
+/* amounts are u128 on the canister, json numbers are only safe up to u64 so larger ones are sent as strings */
+fn amount(value: u128) -> Value {
+    match u64::try_from(value) {
+        Ok(value) => json!(value),
+        Err(_) => json!(value.to_string()),
+    }
+}
+
+fn compensation_json(compensation: &Compensation) -> Value {
+    json!({
+        "min": amount(compensation.min),
+        "max": amount(compensation.max),
+        "currency": compensation.currency,
+    })
+}
+
+fn job_json(job: &Job, base_url: &str) -> Value {
+    let company = job.company.and_then(|id| companies::get_company(id).ok()).map(|company| company.summary());
+
+    json!({
+        "id": job.id,
+        "url": format!("{}/jobs/{}", base_url, job.id),
+        "title": job.title,
+        "description": job.description,
+        "employer": job.employer.to_text(),
+        "company": company,
+        "status": job.status,
+        "created_at": rfc3339(job.created_at),
+        "deadline": job.deadline.map(rfc3339),
+        "compensation": job.compensation.as_ref().map(compensation_json),
+        "location": job.location,
+        "employment_type": job.employment_type,
+        "skills": job.skills,
+        "openings": job.openings,
+        "bounty": job.escrow.as_ref().map(|escrow| amount(escrow.amount)),
+    })
+}
+
+/* https://schema.org/JobPosting, the form search engines and aggregators read job ads in */
+fn job_posting(job: &Job, base_url: &str) -> Value {
+    let company = job.company.and_then(|id| companies::get_company(id).ok()).map(|company| company.summary());
+    let organization = match &company {
+        Some(company) => json!({ "@type": "Organization", "name": company.name, "sameAs": company.website }),
+        None => json!({ "@type": "Organization", "name": job.employer.to_text() }),
+    };
+    let employment_type = match job.employment_type {
+        EmploymentType::FullTime => "FULL_TIME",
+        EmploymentType::Contract => "CONTRACTOR",
+        EmploymentType::Gig => "TEMPORARY",
+        EmploymentType::Internship => "INTERN",
+    };
+
+    let mut posting = json!({
+        "@context": "https://schema.org",
+        "@type": "JobPosting",
+        "identifier": { "@type": "PropertyValue", "name": "Azienda", "value": job.id },
+        "url": format!("{}/jobs/{}", base_url, job.id),
+        "title": job.title,
+        "description": job.description,
+        "datePosted": rfc3339(job.created_at),
+        "employmentType": employment_type,
+        "hiringOrganization": organization,
+        "totalJobOpenings": job.openings,
+    });
+    if let Some(deadline) = job.deadline {
+        posting["validThrough"] = json!(rfc3339(deadline));
+    }
+    if job.location == WorkLocation::Remote {
+        posting["jobLocationType"] = json!("TELECOMMUTE");
+    }
+    if !job.skills.is_empty() {
+        posting["skills"] = json!(job.skills.join(", "));
+    }
+    if let Some(compensation) = &job.compensation {
+        posting["baseSalary"] = json!({
+            "@type": "MonetaryAmount",
+            "currency": compensation.currency,
+            "value": {
+                "@type": "QuantitativeValue",
+                "minValue": amount(compensation.min),
+                "maxValue": amount(compensation.max),
+            },
+        });
+    }
+    posting
+}
+
+fn rss_feed(jobs: &[Job], base_url: &str) -> String {
+    let mut items = String::new();
+    for job in jobs {
+        let link = format!("{}/jobs/{}", base_url, job.id);
+        items.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"true\">{}</guid><description>{}</description><pubDate>{}</pubDate></item>",
+            escape_xml(&job.title),
+            link,
+            link,
+            escape_xml(&job.description),
+            rfc2822(job.created_at),
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><rss version=\"2.0\"><channel><title>Azienda jobs</title><link>{}/jobs</link><description>The newest open jobs on Azienda</description>{}</channel></rss>",
+        base_url, items
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/* the calendar date of a day counted from 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html */
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/* splits a time in nanoseconds into the day since 1970-01-01 and the hour, minute and second of that day */
+fn split_time(nanos: u64) -> (i64, u64, u64, u64) {
+    let secs = nanos / NANOS_PER_SEC;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    (days, rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+/* e.g. 2024-05-01T09:30:00Z */
+fn rfc3339(nanos: u64) -> String {
+    let (days, hour, minute, second) = split_time(nanos);
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+/* e.g. Wed, 01 May 2024 09:30:00 GMT, the date format RSS uses */
+fn rfc2822(nanos: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let (days, hour, minute, second) = split_time(nanos);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_job;
+    use crate::STORAGE;
+
+    const BASE_URL: &str = "https://aaaaa-aa.raw.icp0.io";
+    const NANOS: u64 = NANOS_PER_SEC;
+
+    fn get(method: &str, url: &str) -> HttpResponse {
+        let request = HttpRequest { method: method.to_string(), url: url.to_string(), headers: vec![], body: vec![] };
+        respond(&request, BASE_URL)
+    }
+
+    fn store(job: &Job) {
+        STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+    }
+
+    #[test]
+    fn formats_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc2822(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn formats_leap_days() {
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(rfc3339(1_709_210_096 * NANOS), "2024-02-29T12:34:56Z");
+        assert_eq!(rfc2822(1_709_210_096 * NANOS), "Thu, 29 Feb 2024 12:34:56 GMT");
+        // 2000 is a leap year even though it is a century
+        assert_eq!(rfc3339((951_868_800 - 86_400) * NANOS), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc2822(951_868_800 * NANOS), "Wed, 01 Mar 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn formats_the_last_second_of_a_day() {
+        assert_eq!(rfc3339(946_684_799 * NANOS + NANOS - 1), "1999-12-31T23:59:59Z");
+        assert_eq!(rfc2822(946_684_799 * NANOS), "Fri, 31 Dec 1999 23:59:59 GMT");
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(escape_xml("Tom & Jerry's <b>\"job\"</b>"), "Tom &amp; Jerry&apos;s &lt;b&gt;&quot;job&quot;&lt;/b&gt;");
+        assert_eq!(escape_xml("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn serves_public_jobs() {
+        store(&sample_job(1, "Rust developer"));
+
+        let response = get("GET", "/jobs/1?utm_source=feed");
+        assert_eq!(response.status_code, 200);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["title"], "Rust developer");
+        assert_eq!(body["url"], format!("{}/jobs/1", BASE_URL));
+
+        assert_eq!(get("GET", "/jobs/1/jsonld").status_code, 200);
+    }
+
+    #[test]
+    fn drafts_and_unknown_jobs_are_not_found() {
+        let mut draft = sample_job(1, "Rust developer");
+        draft.status = JobStatus::Draft;
+        store(&draft);
+
+        assert_eq!(get("GET", "/jobs/1").status_code, 404);
+        assert_eq!(get("GET", "/jobs/1/jsonld").status_code, 404);
+        assert_eq!(get("GET", "/jobs/2").status_code, 404);
+        assert_eq!(get("GET", "/jobs/abc").status_code, 400);
+        assert_eq!(get("GET", "/jobs/-1").status_code, 400);
+        assert_eq!(get("GET", "/nowhere").status_code, 404);
+    }
+
+    #[test]
+    fn head_gets_no_body() {
+        store(&sample_job(1, "Rust developer"));
+
+        let head = get("HEAD", "/jobs/1");
+        let full = get("GET", "/jobs/1");
+        assert_eq!(head.status_code, 200);
+        assert_eq!(head.headers, full.headers);
+        assert!(head.body.is_empty());
+        assert!(!full.body.is_empty());
+    }
+
+    #[test]
+    fn only_reads_are_allowed() {
+        assert_eq!(get("POST", "/jobs").status_code, 405);
+    }
+}
@@ -0,0 +1,130 @@
+/* the small part of the ICRC-1/ICRC-2 ledger interface the canister needs to hold bounties in escrow */
+use candid::{CandidType, Nat, Principal};
+
+use crate::Error;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl Account {
+    fn of(owner: Principal) -> Self {
+        Account { owner, subaccount: None }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+fn transfer_failed(message: String) -> Error {
+    Error::TransferFailed { message }
+}
+
+fn to_u128(value: Nat) -> Result<u128, Error> {
+    u128::try_from(value.0).map_err(|_| transfer_failed(String::from("ledger returned an amount that does not fit in 128 bits")))
+}
+
+/* the fee the ledger charges for a transfer */
+pub async fn fee(ledger: Principal) -> Result<u128, Error> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, msg)| transfer_failed(format!("icrc1_fee was rejected ({:?}): {}", code, msg)))?;
+    to_u128(fee)
+}
+
+/* pulls `amount` from `from` into the canister's own account, using the allowance `from` gave the canister.
+   returns the ledger block index */
+pub async fn transfer_from(ledger: Principal, from: Principal, amount: u128, job_id: u64) -> Result<u128, Error> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account::of(from),
+        to: Account::of(ic_cdk::id()),
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(job_id.to_be_bytes().to_vec()),
+        created_at_time: None,
+    };
+
+    let (result,): (Result<Nat, TransferFromError>,) = ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|(code, msg)| transfer_failed(format!("icrc2_transfer_from was rejected ({:?}): {}", code, msg)))?;
+
+    match result {
+        Ok(block) => to_u128(block),
+        Err(err) => Err(transfer_failed(format!("{:?}", err))),
+    }
+}
+
+/* pays `amount` out of the canister's account to `to`. the ledger fee is taken out of the amount,
+   so `to` receives `amount - fee`. an amount the fee would eat entirely is not sent: it stays in the canister's
+   account and counts as paid, so a payout of a few units can never block the job it belongs to.
+   returns the ledger block index, or None when nothing was sent */
+pub async fn payout(ledger: Principal, to: Principal, amount: u128, job_id: u64) -> Result<Option<u128>, Error> {
+    let fee = fee(ledger).await?;
+    if amount <= fee {
+        return Ok(None);
+    }
+
+    let args = TransferArg {
+        from_subaccount: None,
+        to: Account::of(to),
+        amount: Nat::from(amount - fee),
+        fee: Some(Nat::from(fee)),
+        memo: Some(job_id.to_be_bytes().to_vec()),
+        created_at_time: None,
+    };
+
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|(code, msg)| transfer_failed(format!("icrc1_transfer was rejected ({:?}): {}", code, msg)))?;
+
+    match result {
+        Ok(block) => to_u128(block).map(Some),
+        Err(err) => Err(transfer_failed(format!("{:?}", err))),
+    }
+}
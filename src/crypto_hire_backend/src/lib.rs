@@ -1,15 +1,42 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-use ic_cdk::api::time;
-use ic_stable_structures::memory_manager::{self, MemoryId, MemoryManager, VirtualMemory};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::collections::HashMap;
-use std::fmt::format;
 use std::{borrow::Cow, cell::RefCell};
 use ic_stable_structures::storable::Bound;
 //use std::collections::*;
-use ic_cdk::storage;
+
+mod audit;
+mod blobs;
+mod certified;
+mod companies;
+mod config;
+mod disputes;
+mod expiry;
+mod hash_tree;
+mod http;
+mod ledger;
+mod migrations;
+mod milestones;
+mod profiles;
+mod rate_limit;
+mod revisions;
+mod search;
+
+use audit::{AuditPage, Operation};
+use blobs::{Blob, StorageUsage};
+use certified::{CertifiedJob, CertifiedJobPage};
+use companies::{Company, CompanyDetails, CompanySummary};
+use config::{CanisterConfig, ConfigUpdate, InitArgs};
+use disputes::{Dispute, Ruling};
+use http::{HttpRequest, HttpResponse};
+use milestones::{CreateMilestone, Milestone};
+use profiles::{ApplicantProfile, ProfileDetails};
+use rate_limit::{throttle, Budget, RateLimits};
+use revisions::{JobRevision, UpdateJob};
+use search::{SearchJobs, SearchPage};
 
 /*Defining Memory state and IdCell*/
 
@@ -18,53 +45,440 @@ type IdCell = Cell<u64, Memory>;
 //type JobStorage = HashMap<u64, Job>;
 
 //Defining the job application struct
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct Job {
        id: u64,             //the id for the job
        title: String,       // the job title
        description: String, //job description
-      // employer: String,    // the employer that creates the job
+       employer: Principal, // the employer that creates the job
+       company: Option<u64>, // the company the job was posted for
+       status: JobStatus,   // where the job is in its lifecycle
       created_at: u64,  
-       applicant_name: Vec<String>,  
-       accepted_applicants: Option<String>,
+       updated_at: Option<u64>, // when the job was last edited
+       revision: u32,           // starts at 1 and goes up with every edit, see revisions.rs
+       compensation: Option<Compensation>,
+       location: WorkLocation,
+       employment_type: EmploymentType,
+       skills: Vec<String>,   // lowercase tags
+       deadline: Option<u64>, // last moment applications are taken, in nanoseconds
+       openings: u32,         // how many people the job is hiring
+       max_applications: Option<u32>, // the job closes to new applicants once this many have applied
+       accepted_applicants: Vec<Principal>, // the applicants hired, at most `openings` of them
+       escrow: Option<Escrow>, // the bounty held for the job, if it has one
+       milestones: Vec<Milestone>, // the tranches the bounty is paid out in
+       review_window_secs: u64,    // how long the employer has to review a delivered milestone
+       dispute: Option<u64>,       // the open dispute freezing the escrow, if any
+       disputes: Vec<u64>,         // every dispute raised on the job
 }
 
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+//the pay range advertised for a job, in whole units of `currency`. this is informational, a bounty paid on-chain is set separately
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Compensation {
+    min: u128,
+    max: u128,
+    currency: String, // e.g. USD, ICP, ckBTC
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+enum WorkLocation {
+    Remote,
+    Hybrid,
+    OnSite,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+enum EmploymentType {
+    FullTime,
+    Contract,
+    Gig,
+    Internship,
+}
+
+//a bounty held by the canister on an ICRC-1 ledger until the job is done
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Escrow {
+    ledger: Principal, // the ledger the bounty is paid in, fixed when the job is created
+    amount: u128,      // in the ledger's smallest unit
+    released: u128,    // how much of the amount has been paid out through milestones
+    status: EscrowStatus,
+    posting_fee: Option<u128>, // the fee pulled with the bounty when the job was published, kept by the canister
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+enum EscrowStatus {
+    Unfunded,  // the job is a draft and the bounty has not been pulled yet
+    Funding,   // the pull from the employer is in flight
+    Held,      // the canister holds the bounty
+    Releasing, // a payout or refund is in flight
+    Released,  // paid to the accepted applicant
+    Refunded,  // returned to the employer
+}
+
+//an application to a job, stored in APPLICATIONS under (job_id, id)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Application {
+    id: u64,
+    job_id: u64,
+    applicant: Principal, // the principal that applied
+    display_name: String, // the name the applicant wants the employer to see
+    cover_letter: String,
+    submitted_at: u64,
+    status: ApplicationStatus,
+    profile: Option<ProfileDetails>, // the applicant's profile as it was when they applied
+    attachments: Vec<u64>,           // ids of blobs uploaded by the applicant, such as a resume
+    job_revision: u32,               // the revision of the job the applicant applied to
+}
+
+//where an application is in the hiring process
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+enum ApplicationStatus {
+    Submitted,
+    Shortlisted,
+    Rejected,
+    Offered,
+    Hired,
+    Withdrawn,
+}
+
+impl ApplicationStatus {
+    /* Rejected, Hired and Withdrawn are final */
+    fn can_transition_to(&self, next: ApplicationStatus) -> bool {
+        use ApplicationStatus::*;
+        matches!(
+            (self, next),
+            (Submitted, Shortlisted | Rejected | Offered | Hired | Withdrawn)
+                | (Shortlisted, Rejected | Offered | Hired | Withdrawn)
+                | (Offered, Rejected | Hired | Withdrawn)
+        )
+    }
+}
+
+impl Application {
+    fn transition(&mut self, next: ApplicationStatus) -> Result<(), Error> {
+        if self.status.can_transition_to(next) {
+            self.status = next;
+            Ok(())
+        } else {
+            Err(Error::InvalidState {
+                current: format!("{:?}", self.status),
+                action: format!("move application to {:?}", next),
+            })
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 struct CreateJob {
    // id: u64,
     title: String,
     description: String,
   //  applicant_name: Vec<String>,
+    draft: Option<bool>, // keep the job as a draft instead of opening it straight away
+    bounty: Option<u128>, // escrowed on the configured ledger when the job is published
+    company: Option<u64>, // post on behalf of a company the caller owns
+    compensation: Option<Compensation>,
+    location: WorkLocation,
+    employment_type: EmploymentType,
+    skills: Vec<String>,
+    deadline: Option<u64>, // in nanoseconds since the epoch, must be in the future
+    openings: Option<u32>, // defaults to one
+    max_applications: Option<u32>, // defaults to MAX_APPLICANTS_PER_JOB
 }
 
 //the enumeration for the error
-// #[derive(candid::CandidType, Deserialize, Serialize)]
-// enum Error {
-//     JobNotFound {msg: String},
-// }
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+enum Error {
+    NotFound { resource: String, id: String },               // the job or application does not exist
+    Unauthorized { caller: Principal, action: String },      // the caller is not allowed to do this
+    InvalidState { current: String, action: String },        // the action is not allowed in the current state
+    AlreadyApplied { job_id: u64, applicant: Principal },    // the caller already has an application on the job
+    ValidationFailed { field: String, reason: String },      // an input did not pass validation
+    CapacityExceeded { resource: String, limit: u64 },       // a limit on the number of items was reached
+    TransferFailed { message: String },                      // the ledger did not move the funds
+    RateLimited { retry_after_secs: u64 },                   // too many create_job or apply_to_job calls, try again later
+}
+
+impl Error {
+    fn job_not_found(job_id: u64) -> Self {
+        Error::NotFound { resource: String::from("job"), id: job_id.to_string() }
+    }
+
+    fn application_not_found(application_id: u64) -> Self {
+        Error::NotFound { resource: String::from("application"), id: application_id.to_string() }
+    }
+
+    fn unauthorized(action: &str) -> Self {
+        Error::Unauthorized { caller: caller(), action: action.to_string() }
+    }
+
+    fn validation(field: &str, reason: &str) -> Self {
+        Error::ValidationFailed { field: field.to_string(), reason: reason.to_string() }
+    }
+}
+
+/* rejects text that is empty (when required) or longer than `max` bytes */
+fn validate_text(field: &str, value: &str, required: bool, max: usize) -> Result<(), Error> {
+    if required && value.trim().is_empty() {
+        return Err(Error::validation(field, "must not be empty"));
+    }
+    if value.len() > max {
+        return Err(Error::validation(field, &format!("must be at most {} bytes", max)));
+    }
+    Ok(())
+}
+
+/* rejects links that are not http(s) urls of at most `max` bytes */
+fn validate_url(field: &str, value: &str, max: usize) -> Result<(), Error> {
+    validate_text(field, value, true, max)?;
+    if !value.starts_with("https://") && !value.starts_with("http://") {
+        return Err(Error::validation(field, "must start with http:// or https://"));
+    }
+    Ok(())
+}
+
+/* limits on the posting details of a job */
+const MAX_SKILLS: usize = 20;
+const MAX_SKILL_LEN: usize = 40;
+const MAX_CURRENCY_LEN: usize = 10;
+const MAX_OPENINGS: u32 = 100;
+
+fn validate_compensation(compensation: &Compensation) -> Result<(), Error> {
+    if compensation.max == 0 {
+        return Err(Error::validation("compensation", "max must be greater than zero"));
+    }
+    if compensation.min > compensation.max {
+        return Err(Error::validation("compensation", "min must not be greater than max"));
+    }
+    if compensation.currency.is_empty()
+        || compensation.currency.len() > MAX_CURRENCY_LEN
+        || !compensation.currency.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(Error::validation(
+            "compensation",
+            &format!("currency must be 1 to {} letters or digits", MAX_CURRENCY_LEN),
+        ));
+    }
+    Ok(())
+}
+
+/* trims and lowercases skill tags and drops duplicates, so "Rust" and "rust " are the same skill */
+fn normalize_skills(skills: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut normalized: Vec<String> = vec![];
+    for skill in skills {
+        let skill = skill.trim().to_lowercase();
+        validate_text("skills", &skill, true, MAX_SKILL_LEN)?;
+        if !normalized.contains(&skill) {
+            normalized.push(skill);
+        }
+    }
+
+    if normalized.len() > MAX_SKILLS {
+        return Err(Error::CapacityExceeded { resource: String::from("skills"), limit: MAX_SKILLS as u64 });
+    }
+    Ok(normalized)
+}
+
+fn validate_deadline(deadline: Option<u64>) -> Result<(), Error> {
+    match deadline {
+        Some(deadline) if deadline <= time() => Err(Error::validation("deadline", "must be in the future")),
+        _ => Ok(()),
+    }
+}
+
+/* the most applications a single job will hold, admins can set a lower limit for every job in the config
+   and employers a lower one still per job */
+const MAX_APPLICANTS_PER_JOB: usize = 100;
+
+fn validate_max_applications(max_applications: Option<u32>) -> Result<(), Error> {
+    let limit = config::size_limits().max_applications_per_job;
+    match max_applications {
+        Some(max) if !(1..=limit).contains(&max) => {
+            Err(Error::validation("max_applications", &format!("must be between 1 and {}", limit)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/* how many applications a job takes before it closes to new applicants. withdrawn applications still count */
+fn application_limit(job: &Job) -> usize {
+    let limit = config::size_limits().max_applications_per_job;
+    job.max_applications.map_or(limit, |max| max.min(limit)) as usize
+}
+
+/* size limits on user supplied text, in bytes, so a job can never grow without bound. admins can lower
+   the limits on titles, descriptions and cover letters in the config */
+const MAX_TITLE_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 10_000;
+const MAX_DISPLAY_NAME_LEN: usize = 100;
+const MAX_COVER_LETTER_LEN: usize = 5_000;
+
+/* page sizes for list_jobs, and how many jobs one call may look at before handing back a cursor */
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_JOBS_SCANNED: usize = 1_000;
 
-#[derive(candid::CandidType, Deserialize, Serialize)]
+//the lifecycle of a job
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 enum JobStatus{
-    AcceptJob,
-    JobWithdrawn,
-    JobCancelled,
+    Draft,     // created but not yet visible to applicants
+    Open,      // accepting applications
+    Filled,    // an applicant has been accepted
+    Closed,    // no longer accepting applications
+    Cancelled, // withdrawn by the employer, kept for history
+}
+
+impl JobStatus {
+    /* the transitions a job is allowed to make, anything else is rejected */
+    fn can_transition_to(&self, next: JobStatus) -> bool {
+        use JobStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Open)
+                | (Draft, Cancelled)
+                | (Open, Filled)
+                | (Open, Closed)
+                | (Open, Cancelled)
+                | (Filled, Closed)
+                | (Closed, Open)
+                | (Closed, Filled)
+                | (Closed, Cancelled)
+        )
+    }
+}
+
+impl Job {
+    fn transition(&mut self, next: JobStatus) -> Result<(), Error> {
+        self.ensure_escrow_idle()?;
+
+        if self.status.can_transition_to(next) {
+            self.status = next;
+            Ok(())
+        } else {
+            Err(Error::InvalidState {
+                current: format!("{:?}", self.status),
+                action: format!("move to {:?}", next),
+            })
+        }
+    }
+
+    /* nothing may change the job while a ledger call for its escrow is in flight,
+       or while a dispute has the escrow frozen */
+    fn ensure_escrow_idle(&self) -> Result<(), Error> {
+        if let Some(dispute_id) = self.dispute {
+            return Err(Error::InvalidState {
+                current: format!("dispute {} open", dispute_id),
+                action: String::from("change the job"),
+            });
+        }
+
+        match &self.escrow {
+            Some(escrow) if matches!(escrow.status, EscrowStatus::Funding | EscrowStatus::Releasing) => {
+                Err(Error::InvalidState {
+                    current: format!("escrow {:?}", escrow.status),
+                    action: String::from("change the job"),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /* the applicant a bounty is paid to. a job with a bounty only has one opening, so this is its only hire */
+    fn worker(&self) -> Option<Principal> {
+        self.accepted_applicants.first().copied()
+    }
+
+    fn ensure_status(&self, allowed: &[JobStatus], action: &str) -> Result<(), Error> {
+        if allowed.contains(&self.status) {
+            Ok(())
+        } else {
+            Err(Error::InvalidState {
+                current: format!("{:?}", self.status),
+                action: action.to_string(),
+            })
+        }
+    }
+}
+
+//the order list_jobs returns jobs in, by creation time
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+//the filters and paging options for list_jobs, every field is optional
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ListJobs {
+    cursor: Option<u64>,         // the next_cursor of the previous page
+    limit: Option<u32>,          // how many jobs to return, capped at MAX_PAGE_SIZE
+    status: Option<JobStatus>,
+    employer: Option<Principal>,
+    created_after: Option<u64>,  // inclusive, in nanoseconds
+    created_before: Option<u64>, // exclusive, in nanoseconds
+    order: Option<SortOrder>,
+}
+
+//one page of list_jobs, next_cursor is None once there is nothing left to read
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct JobPage {
+    jobs: Vec<Job>,
+    next_cursor: Option<u64>,
+}
+
+//a job together with the company that posted it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct JobDetails {
+    job: Job,
+    company: Option<CompanySummary>,
 }
 
 //next , we implement a trait that must be implemented for a struct that is stored in a stable struct
+//the schema versions new records are written with, see migrations.rs
+const JOB_VERSION: u8 = 4;
+const APPLICATION_VERSION: u8 = 3;
+
 impl Storable for Job {
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(JOB_VERSION, self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        match migrations::split(&bytes) {
+            (0, payload) => {
+                let job = migrations::JobV1::from(migrations::decode::<migrations::JobV0>(payload));
+                migrations::JobV3::from(migrations::JobV2::from(job)).into()
+            }
+            (1, payload) => {
+                let job = migrations::JobV2::from(migrations::decode::<migrations::JobV1>(payload));
+                migrations::JobV3::from(job).into()
+            }
+            (2, payload) => migrations::JobV3::from(migrations::decode::<migrations::JobV2>(payload)).into(),
+            (3, payload) => migrations::decode::<migrations::JobV3>(payload).into(),
+            (4, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Job", version),
+        }
     }
 
-    const BOUND: Bound = Bound::Bounded {
-        max_size: 1024,
-        is_fixed_size: false,
-    };
+    // jobs grow with their description, the inputs are size checked instead
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Application {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(APPLICATION_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::ApplicationV2::from(migrations::decode::<migrations::ApplicationV1>(payload)).into(),
+            (2, payload) => migrations::decode::<migrations::ApplicationV2>(payload).into(),
+            (3, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("Application", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 thread_local! {
@@ -83,12 +497,113 @@ thread_local! {
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
         ));
+
+    /*the counter used to hand out application ids*/
+    static APPLICATION_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))), 0).expect("cannot create application counter")
+    );
+
+    /*the applications to every job, keyed by (job_id, application_id) so a job's applications sit together*/
+    static APPLICATIONS: RefCell<StableBTreeMap<(u64, u64), Application, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        ));
+
+    /*the ICRC-1 ledger new bounties are paid in, set by an admin*/
+    static LEDGER: RefCell<Cell<Option<Principal>, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))), None).expect("cannot create ledger cell")
+    );
+}
+
+fn get_job(job_id: u64) -> Result<Job, Error> {
+    STORAGE
+        .with(|storage| storage.borrow().get(&job_id))
+        .ok_or(Error::job_not_found(job_id))
+}
+
+/* every change to a job goes through here, so the certified tree stays in step with storage */
+fn save_job(job: &Job) {
+    STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+    certified::certify(job);
+}
+
+/* every application to a job, in the order they were submitted */
+fn job_applications(job_id: u64) -> Vec<Application> {
+    APPLICATIONS.with(|applications| {
+        applications
+            .borrow()
+            .range((job_id, 0)..=(job_id, u64::MAX))
+            .map(|(_, application)| application)
+            .collect()
+    })
+}
+
+/* the application a principal made to a job, if any */
+fn find_application(job_id: u64, applicant: Principal) -> Option<Application> {
+    job_applications(job_id)
+        .into_iter()
+        .find(|application| application.applicant == applicant)
+}
+
+fn get_application(job_id: u64, application_id: u64) -> Result<Application, Error> {
+    APPLICATIONS
+        .with(|applications| applications.borrow().get(&(job_id, application_id)))
+        .ok_or(Error::application_not_found(application_id))
+}
+
+fn next_application_id() -> u64 {
+    APPLICATION_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter.borrow_mut().set(current_value + 1).expect("Cannot increment application id counter");
+        current_value + 1
+    })
+}
+
+fn save_application(application: &Application) {
+    APPLICATIONS.with(|applications| {
+        applications
+            .borrow_mut()
+            .insert((application.job_id, application.id), application.clone())
+    });
 }
 
 
 /*below is our function to create the job */
-#[ic_cdk::update]
-fn create_job(job: CreateJob) -> Job {
+#[ic_cdk::update(guard = "throttle")]
+fn create_job(job: CreateJob) -> Result<Job, Error> {
+    rate_limit::take(Budget::CreateJob)?;
+    let limits = config::size_limits();
+    validate_text("title", &job.title, true, limits.max_title_len as usize)?;
+    validate_text("description", &job.description, false, limits.max_description_len as usize)?;
+    if let Some(compensation) = &job.compensation {
+        validate_compensation(compensation)?;
+    }
+    let skills = normalize_skills(job.skills)?;
+    validate_deadline(job.deadline)?;
+    if let Some(company_id) = job.company {
+        let company = companies::get_company(company_id)?;
+        companies::ensure_owner(&company, "post a job for the company")?;
+    }
+    let openings = job.openings.unwrap_or(1);
+    if !(1..=MAX_OPENINGS).contains(&openings) {
+        return Err(Error::validation("openings", &format!("must be between 1 and {}", MAX_OPENINGS)));
+    }
+    if job.bounty.is_some() && openings > 1 {
+        return Err(Error::validation("openings", "a job with a bounty hires a single applicant"));
+    }
+    validate_max_applications(job.max_applications)?;
+
+    let escrow = match job.bounty {
+        None => None,
+        Some(0) => return Err(Error::validation("bounty", "must be greater than zero")),
+        Some(amount) => {
+            let ledger = LEDGER
+                .with(|cell| *cell.borrow().get())
+                .ok_or(Error::validation("bounty", "no ledger is configured for bounties"))?;
+            Some(Escrow { ledger, amount, released: 0, status: EscrowStatus::Unfunded, posting_fee: None })
+        }
+    };
+
     let id = ID_COUNTER.with(|counter|{
         let current_value = *counter.borrow().get();
         counter.borrow_mut().set(current_value + 1).expect("Cannot increment id counter");
@@ -99,38 +614,103 @@ fn create_job(job: CreateJob) -> Job {
         id,
         title: job.title,
         description: job.description,
+        employer: caller(),
+        company: job.company,
+        // a job with a bounty stays a draft until publish_job has pulled the bounty into escrow
+        status: if job.draft.unwrap_or(false) || escrow.is_some() { JobStatus::Draft } else { JobStatus::Open },
         created_at: time(),
-        applicant_name: vec![],
-        accepted_applicants: None,
+        updated_at: None,
+        revision: 1,
+        compensation: job.compensation,
+        location: job.location,
+        employment_type: job.employment_type,
+        skills,
+        deadline: job.deadline,
+        openings,
+        max_applications: job.max_applications,
+        accepted_applicants: vec![],
+        escrow,
+        milestones: vec![],
+        review_window_secs: milestones::DEFAULT_REVIEW_WINDOW_SECS,
+        dispute: None,
+        disputes: vec![],
     };
 
-    STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
-    job
+    save_job(&job);
+    expiry::track(job.id, None, job.deadline);
+    search::index_job(&job);
+    audit::record(Operation::JobCreated, Some(job.id), job.company, None);
+    Ok(job)
 }
 // fn do_insert(job: &Job) {
 //     STORAGE.with(|service| service.borrow_mut().insert(job.id, job.clone()));
 // }
 
 
-/*this is our function to apply for the job*/
-#[ic_cdk::update]
-fn apply_to_job(job_id: u64, applicant_name: String) -> Result<(), String> {
+/*this is our function to apply for the job. an applicant with a profile sends a copy of it along,
+  and may leave the display name empty to use the one on their profile. attachments are blobs the
+  applicant uploaded, the employer of the job can read them once the application is in*/
+#[ic_cdk::update(guard = "throttle")]
+fn apply_to_job(job_id: u64, display_name: String, cover_letter: String, attachments: Vec<u64>) -> Result<Application, Error> {
+    rate_limit::take(Budget::ApplyToJob)?;
+    let profile = profiles::profile_details(caller());
+    validate_text("display_name", &display_name, profile.is_none(), MAX_DISPLAY_NAME_LEN)?;
+    let display_name = match &profile {
+        Some(profile) if display_name.trim().is_empty() => profile.display_name.clone(),
+        _ => display_name,
+    };
+    let max_cover_letter_len = config::size_limits().max_cover_letter_len as usize;
+    validate_text("cover_letter", &cover_letter, false, max_cover_letter_len)?;
+    blobs::validate_attachments(&attachments)?;
+
     STORAGE.with(|storage| {
-        let mut job_opt = {
-            let mut storage_ref = storage.borrow_mut();
+        let job_opt = {
+            let storage_ref = storage.borrow_mut();
             storage_ref.get(&job_id).clone()
         };
 
         if let Some(mut job) = job_opt {
-            job.applicant_name.push(applicant_name);
+            let applicant = caller();
+            let existing = job_applications(job_id);
+            if existing.iter().any(|a| a.applicant == applicant) {
+                return Err(Error::AlreadyApplied { job_id, applicant });
+            }
+            // a job that closed because it was full says so, rather than just that it is closed
+            let limit = application_limit(&job);
+            if existing.len() >= limit {
+                return Err(Error::CapacityExceeded { resource: String::from("applications"), limit: limit as u64 });
+            }
+            job.ensure_status(&[JobStatus::Open], "apply")?;
+            if expiry::has_passed(job.deadline, time()) {
+                return Err(Error::InvalidState { current: String::from("past the deadline"), action: String::from("apply") });
+            }
 
-            STORAGE.with(|storage| {
-                storage.borrow_mut().insert(job.id, job);
-            });
+            let id = next_application_id();
 
-            Ok(())
+            let application = Application {
+                id,
+                job_id,
+                applicant,
+                display_name,
+                cover_letter,
+                submitted_at: time(),
+                status: ApplicationStatus::Submitted,
+                profile,
+                attachments,
+                job_revision: job.revision,
+            };
+            save_application(&application);
+            blobs::attach(job_id, &application.attachments);
+            audit::record(Operation::ApplicationSubmitted, Some(job_id), Some(id), None);
+
+            if existing.len() + 1 >= limit && job.transition(JobStatus::Closed).is_ok() {
+                save_job(&job);
+                audit::record(Operation::ApplicationLimitReached, Some(job_id), None, None);
+            }
+
+            Ok(application)
         } else {
-            Err(String::from("Job not found"))
+            Err(Error::job_not_found(job_id))
         }
     })
 }
@@ -148,22 +728,28 @@ fn apply_to_job(job_id: u64, applicant_name: String) -> Result<(), String> {
 // }
 
 /* this is our application withdrawn function*/
-#[ic_cdk::update]
-fn withdraw_application(job_id: u64, applicant_name: String) -> Result<(), String> {
-    let mut job_opt = STORAGE.with(|storage| {
+#[ic_cdk::update(guard = "throttle")]
+fn withdraw_application(job_id: u64) -> Result<(), Error> {
+    let job_opt = STORAGE.with(|storage| {
         storage.borrow().get(&job_id).clone()
     });
 
-    if let Some(mut job) = job_opt {
-        job.applicant_name.retain(|name| name != &applicant_name);
+    if let Some(job) = job_opt {
+        job.ensure_status(&[JobStatus::Open, JobStatus::Closed], "withdraw an application")?;
 
-        STORAGE.with(|storage| {
-            storage.borrow_mut().insert(job.id, job);
-        });
+        let applicant = caller();
+        let mut application = find_application(job_id, applicant).ok_or(Error::NotFound {
+            resource: String::from("application"),
+            id: applicant.to_text(),
+        })?;
+
+        application.transition(ApplicationStatus::Withdrawn)?;
+        save_application(&application);
+        audit::record(Operation::ApplicationWithdrawn, Some(job_id), Some(application.id), None);
 
         Ok(())
     } else {
-        Err(String::from("Job not found"))
+        Err(Error::job_not_found(job_id))
     }
 }
 
@@ -181,40 +767,246 @@ fn withdraw_application(job_id: u64, applicant_name: String) -> Result<(), Strin
 // }
 
 
-/* cancel job function*/
-#[ic_cdk::update]
-fn cancel_job(job_id: u64) -> Result<(), String> {
-    STORAGE.with(|storage| {
-        if storage.borrow_mut().remove(&job_id).is_some() {
-            Ok(())
-        }else{
-            Err(String::from("Job not found"))
+/* who manages a job on the employer side: the owners of the company it was posted for, as they are now, or
+   the principal that created it if it has no company. bounties are still pulled from and refunded to
+   the principal that created the job */
+fn is_employer(job: &Job, principal: &Principal) -> bool {
+    match job.company.and_then(|id| companies::get_company(id).ok()) {
+        Some(company) => companies::is_owner(&company, principal),
+        None => job.employer == *principal,
+    }
+}
+
+fn ensure_employer(job: &Job, action: &str) -> Result<(), Error> {
+    if is_employer(job, &caller()) {
+        Ok(())
+    } else {
+        Err(Error::unauthorized(action))
+    }
+}
+
+/* marks the escrow of a stored job as busy and returns what the ledger call needs.
+   the job is saved before the call so a second message sees the escrow as busy */
+fn lock_escrow(job: &mut Job, busy: EscrowStatus) -> (Principal, u128) {
+    let escrow = job.escrow.as_mut().expect("job has no escrow");
+    escrow.status = busy;
+    let locked = (escrow.ledger, escrow.amount);
+    save_job(job);
+    locked
+}
+
+/* records the outcome of a ledger call on the job's escrow, on failure the escrow goes back to `previous` */
+fn settle_escrow<T>(job_id: u64, result: Result<T, Error>, done: EscrowStatus, previous: EscrowStatus) -> Result<Job, Error> {
+    let mut job = get_job(job_id)?;
+    if let Some(escrow) = job.escrow.as_mut() {
+        escrow.status = if result.is_ok() { done } else { previous };
+    }
+    save_job(&job);
+    result.map(|_| job)
+}
+
+/* opens a draft job, or reopens a closed one, to applicants.
+   a draft with a bounty is funded first, the employer must have approved the canister to spend it */
+#[ic_cdk::update(guard = "throttle")]
+async fn publish_job(job_id: u64) -> Result<(), Error> {
+    let job = get_job(job_id)?;
+    ensure_employer(&job, "publish the job")?;
+    let ledger_fee = match job.escrow.filter(|e| e.status == EscrowStatus::Unfunded) {
+        Some(escrow) => ledger::fee(escrow.ledger).await?,
+        None => 0,
+    };
+
+    // the job may have changed while the fee was looked up
+    let mut job = get_job(job_id)?;
+    job.clone().transition(JobStatus::Open)?;
+    if expiry::has_passed(job.deadline, time()) {
+        return Err(Error::validation("deadline", "has passed, move it with set_deadline first"));
+    }
+    if job_applications(job_id).len() >= application_limit(&job) {
+        return Err(Error::validation("max_applications", "has been reached, raise it with update_job first"));
+    }
+
+    if let Some(escrow) = job.escrow.as_ref().filter(|e| e.status == EscrowStatus::Unfunded) {
+        // checked before the escrow is locked, an error after that would leave it stuck in Funding
+        if escrow.amount <= ledger_fee {
+            return Err(Error::validation("bounty", &format!("must be more than the ledger fee of {}", ledger_fee)));
         }
-    })
+        let fee = config::posting_fee();
+        let total = escrow.amount.checked_add(fee).ok_or(Error::validation("bounty", "is too large to add the posting fee to"))?;
+        let (ledger_id, _) = lock_escrow(&mut job, EscrowStatus::Funding);
+        let result = ledger::transfer_from(ledger_id, job.employer, total, job_id).await;
+        job = settle_escrow(job_id, result, EscrowStatus::Held, EscrowStatus::Unfunded)?;
+        if let Some(escrow) = job.escrow.as_mut().filter(|_| fee > 0) {
+            escrow.posting_fee = Some(fee);
+        }
+    }
+
+    job.transition(JobStatus::Open)?;
+    save_job(&job);
+    audit::record(Operation::JobPublished, Some(job_id), None, None);
+    Ok(())
+}
+
+/* stops an open job from accepting applications. a filled job is closed by complete_job */
+#[ic_cdk::update(guard = "throttle")]
+fn close_job(job_id: u64) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    ensure_employer(&job, "close the job")?;
+    job.ensure_status(&[JobStatus::Open], "close the job")?;
+    job.transition(JobStatus::Closed)?;
+
+    save_job(&job);
+    audit::record(Operation::JobClosed, Some(job_id), None, None);
+    Ok(())
+}
+
+/* cancel job function, the job is kept in storage so its history stays queryable.
+   a bounty held for a job nobody was hired for goes back to the employer, unless the ledger fee would eat it */
+#[ic_cdk::update(guard = "throttle")]
+async fn cancel_job(job_id: u64) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    ensure_employer(&job, "cancel the job")?;
+    job.clone().transition(JobStatus::Cancelled)?;
+
+    if job.escrow.as_ref().is_some_and(|e| e.status == EscrowStatus::Held) {
+        let (ledger_id, amount) = lock_escrow(&mut job, EscrowStatus::Releasing);
+        let result = ledger::payout(ledger_id, job.employer, amount, job_id).await;
+        job = settle_escrow(job_id, result, EscrowStatus::Refunded, EscrowStatus::Held)?;
+    }
+
+    job.transition(JobStatus::Cancelled)?;
+    save_job(&job);
+    search::remove_job(job_id);
+    audit::record(Operation::JobCancelled, Some(job_id), None, None);
+    Ok(())
+}
+
+/* marks a filled job as done and pays whatever is left of the bounty to the accepted applicant,
+   milestones that were not approved yet are paid as part of it. a remainder the ledger fee would eat is not sent */
+#[ic_cdk::update(guard = "throttle")]
+async fn complete_job(job_id: u64) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    ensure_employer(&job, "complete the job")?;
+    job.ensure_status(&[JobStatus::Filled], "complete the job")?;
+    job.ensure_escrow_idle()?;
+
+    if job.escrow.as_ref().is_some_and(|e| e.status == EscrowStatus::Held) {
+        let worker = job.worker().expect("a filled job has an accepted applicant");
+        let (ledger_id, amount) = lock_escrow(&mut job, EscrowStatus::Releasing);
+        let released = job.escrow.as_ref().map_or(0, |e| e.released);
+        let result = ledger::payout(ledger_id, worker, amount - released, job_id).await;
+        job = settle_escrow(job_id, result, EscrowStatus::Released, EscrowStatus::Held)?;
+        if let Some(escrow) = job.escrow.as_mut() {
+            escrow.released = escrow.amount;
+        }
+    }
+    milestones::release_all(&mut job);
+
+    job.transition(JobStatus::Closed)?;
+    save_job(&job);
+    audit::record(Operation::JobCompleted, Some(job_id), None, job.worker());
+    Ok(())
+}
+
+/* points new bounties at an ICRC-1 ledger, jobs that already have a bounty keep theirs */
+#[ic_cdk::update(guard = "throttle")]
+fn set_ledger(ledger_id: Principal) -> Result<(), Error> {
+    config::ensure_admin("set the ledger")?;
+
+    LEDGER
+        .with(|cell| cell.borrow_mut().set(Some(ledger_id)))
+        .expect("cannot set ledger");
+    audit::record(Operation::LedgerSet, None, None, Some(ledger_id));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_ledger() -> Option<Principal> {
+    LEDGER.with(|cell| *cell.borrow().get())
 }
  
- /*job acceptance function */
- #[ic_cdk::update]
- fn accept_job(job_id: u64, applicant_name: String) -> Result<(), String> {
-    let mut job_opt = STORAGE.with(|storage| {
+ /*job acceptance function. a job that closed to new applicants, because it reached its application limit,
+   its deadline passed or the employer closed it, can still hire the applicants it has */
+ #[ic_cdk::update(guard = "throttle")]
+ fn accept_job(job_id: u64, application_id: u64) -> Result<(), Error> {
+    let job_opt = STORAGE.with(|storage| {
         storage.borrow().get(&job_id).clone()
     });
 
     if let Some(mut job) = job_opt {
-        if job.applicant_name.contains(&applicant_name) {
-            job.accepted_applicants = Some(applicant_name);
+        ensure_employer(&job, "accept an applicant")?;
 
-            STORAGE.with(|storage| {
-                storage.borrow_mut().insert(job.id, job);
-            });
+        job.ensure_status(&[JobStatus::Open, JobStatus::Closed], "accept an applicant")?;
+        job.ensure_escrow_idle()?;
+        if job.accepted_applicants.len() >= job.openings as usize {
+            return Err(Error::CapacityExceeded { resource: String::from("openings"), limit: job.openings as u64 });
+        }
 
-            Ok(())
-        } else {
-            Err(String::from("Applicant not found"))
+        let mut application = get_application(job_id, application_id)?;
+        application.transition(ApplicationStatus::Hired)?;
+        job.accepted_applicants.push(application.applicant);
+        // the job is filled once the last opening is taken
+        if job.accepted_applicants.len() == job.openings as usize {
+            job.transition(JobStatus::Filled)?;
         }
+
+        save_application(&application);
+        save_job(&job);
+        audit::record(Operation::ApplicantHired, Some(job_id), Some(application_id), Some(application.applicant));
+
+        Ok(())
     } else {
-        Err(String::from("Job not found"))
+        Err(Error::job_not_found(job_id))
+    }
+}
+
+/* lets the employer shortlist, reject or make an offer on an application.
+   hiring goes through accept_job and withdrawing is left to the applicant */
+#[ic_cdk::update(guard = "throttle")]
+fn update_application_status(job_id: u64, application_id: u64, status: ApplicationStatus) -> Result<Application, Error> {
+    let job = STORAGE
+        .with(|storage| storage.borrow().get(&job_id))
+        .ok_or(Error::job_not_found(job_id))?;
+    ensure_employer(&job, "update an application")?;
+
+    if !matches!(
+        status,
+        ApplicationStatus::Shortlisted | ApplicationStatus::Rejected | ApplicationStatus::Offered
+    ) {
+        return Err(Error::validation("status", "must be Shortlisted, Rejected or Offered"));
+    }
+
+    let mut application = get_application(job_id, application_id)?;
+    application.transition(status)?;
+    save_application(&application);
+    audit::record(Operation::ApplicationStatusChanged, Some(job_id), Some(application_id), Some(application.applicant));
+
+    Ok(application)
+}
+
+/* an application can be read by the employer of the job and by the applicant */
+#[ic_cdk::query]
+fn fetch_application(job_id: u64, application_id: u64) -> Result<Application, Error> {
+    let job = STORAGE
+        .with(|storage| storage.borrow().get(&job_id))
+        .ok_or(Error::job_not_found(job_id))?;
+    let application = get_application(job_id, application_id)?;
+
+    if application.applicant != caller() {
+        ensure_employer(&job, "view an application")?;
     }
+    Ok(application)
+}
+
+/* every application to a job, for its employer */
+#[ic_cdk::query]
+fn list_applications(job_id: u64) -> Result<Vec<Application>, Error> {
+    let job = STORAGE
+        .with(|storage| storage.borrow().get(&job_id))
+        .ok_or(Error::job_not_found(job_id))?;
+    ensure_employer(&job, "list applications")?;
+
+    Ok(job_applications(job_id))
 }
 
 
@@ -236,15 +1028,179 @@ fn cancel_job(job_id: u64) -> Result<(), String> {
 //  }
 
  #[ic_cdk::query]
- fn fetch_job(job_id: u64) -> Result<Job, String> {
+ fn fetch_job(job_id: u64) -> Result<Job, Error> {
     STORAGE.with(|storage|{
         if let Some(job) = storage.borrow().get(&job_id){
             Ok(job.clone())
         } else {
-            Err("Job not found".to_string())
+            Err(Error::job_not_found(job_id))
+        }
+    })
+ }
+
+
+ /* the entry after `cursor` in the given order, or the first one when there is no cursor.
+    job ids are handed out in creation order, so walking ids is walking created_at */
+ fn next_job(
+    storage: &StableBTreeMap<u64, Job, Memory>,
+    cursor: Option<u64>,
+    order: SortOrder,
+ ) -> Option<(u64, Job)> {
+    match (order, cursor) {
+        (SortOrder::Ascending, None) => storage.first_key_value(),
+        (SortOrder::Ascending, Some(id)) => storage
+            .range((std::ops::Bound::Excluded(id), std::ops::Bound::Unbounded))
+            .next(),
+        (SortOrder::Descending, None) => storage.last_key_value(),
+        (SortOrder::Descending, Some(id)) => storage.iter_upper_bound(&id).next(),
+    }
+ }
+
+ #[ic_cdk::query]
+ fn list_jobs(request: ListJobs) -> JobPage {
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    let order = request.order.unwrap_or(SortOrder::Ascending);
+
+    STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let mut jobs = vec![];
+        let mut cursor = request.cursor;
+        let mut scanned = 0;
+
+        while jobs.len() < limit && scanned < MAX_JOBS_SCANNED {
+            let Some((id, job)) = next_job(&storage, cursor, order) else {
+                return JobPage { jobs, next_cursor: None };
+            };
+            scanned += 1;
+            cursor = Some(id);
+
+            // once we walk past the requested time range nothing further can match
+            let past_range = match order {
+                SortOrder::Ascending => request.created_before.is_some_and(|t| job.created_at >= t),
+                SortOrder::Descending => request.created_after.is_some_and(|t| job.created_at < t),
+            };
+            if past_range {
+                return JobPage { jobs, next_cursor: None };
+            }
+
+            let matches = request.status.is_none_or(|s| job.status == s)
+                && request.employer.is_none_or(|e| job.employer == e)
+                && request.created_after.is_none_or(|t| job.created_at >= t)
+                && request.created_before.is_none_or(|t| job.created_at < t);
+            if matches {
+                jobs.push(job);
+            }
         }
+
+        JobPage { jobs, next_cursor: cursor }
     })
  }
 
 
+ /* a job with a summary of the company it was posted for, if any */
+ #[ic_cdk::query]
+ fn fetch_job_details(job_id: u64) -> Result<JobDetails, Error> {
+    let job = get_job(job_id)?;
+    let company = match job.company {
+        Some(company_id) => Some(companies::get_company(company_id)?.summary()),
+        None => None,
+    };
+    Ok(JobDetails { job, company })
+ }
+
+ #[ic_cdk::init]
+ fn init(args: Option<InitArgs>) {
+    config::apply_init_args(args);
+    certified::rebuild();
+    search::ensure_index();
+    expiry::start_expiry_timer();
+ }
+
+ #[ic_cdk::post_upgrade]
+ fn post_upgrade(args: Option<InitArgs>) {
+    migrations::migrate_legacy_jobs(caller());
+    config::apply_init_args(args);
+    certified::rebuild();
+    search::ensure_index();
+    milestones::rearm_review_timers();
+    expiry::start_expiry_timer();
+ }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* an open job posted by a made up employer, for tests to change as they need */
+    pub(crate) fn sample_job(id: u64, title: &str) -> Job {
+        Job {
+            id,
+            title: title.to_string(),
+            description: String::new(),
+            employer: Principal::from_slice(&[1; 29]),
+            company: None,
+            status: JobStatus::Open,
+            created_at: 1_000,
+            updated_at: None,
+            revision: 1,
+            compensation: None,
+            location: WorkLocation::Remote,
+            employment_type: EmploymentType::FullTime,
+            skills: vec![],
+            deadline: None,
+            openings: 1,
+            max_applications: None,
+            accepted_applicants: vec![],
+            escrow: None,
+            milestones: vec![],
+            review_window_secs: milestones::DEFAULT_REVIEW_WINDOW_SECS,
+            dispute: None,
+            disputes: vec![],
+        }
+    }
+
+    #[test]
+    fn a_refund_the_ledger_fee_would_eat_still_refunds_the_escrow() {
+        let mut job = sample_job(1, "Rust developer");
+        job.escrow = Some(Escrow {
+            ledger: Principal::management_canister(),
+            amount: 5,
+            released: 0,
+            status: EscrowStatus::Releasing,
+            posting_fee: None,
+        });
+        STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+
+        // what ledger::payout returns when the amount does not cover the fee
+        let dust: Result<Option<u128>, Error> = Ok(None);
+        let mut job = settle_escrow(1, dust, EscrowStatus::Refunded, EscrowStatus::Held).unwrap();
+        assert_eq!(job.escrow.as_ref().map(|e| e.status), Some(EscrowStatus::Refunded));
+        assert!(job.transition(JobStatus::Cancelled).is_ok());
+    }
+
+    #[test]
+    fn a_failed_refund_puts_the_escrow_back() {
+        let mut job = sample_job(1, "Rust developer");
+        job.escrow = Some(Escrow {
+            ledger: Principal::management_canister(),
+            amount: 5_000,
+            released: 0,
+            status: EscrowStatus::Releasing,
+            posting_fee: None,
+        });
+        STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+
+        let failed: Result<Option<u128>, Error> = Err(Error::TransferFailed { message: String::from("TemporarilyUnavailable") });
+        assert!(settle_escrow(1, failed, EscrowStatus::Refunded, EscrowStatus::Held).is_err());
+        assert_eq!(get_job(1).unwrap().escrow.map(|e| e.status), Some(EscrowStatus::Held));
+    }
+
+    #[test]
+    fn closed_jobs_can_still_hire() {
+        assert!(JobStatus::Closed.can_transition_to(JobStatus::Filled));
+        assert!(JobStatus::Closed.can_transition_to(JobStatus::Open));
+        assert!(!JobStatus::Cancelled.can_transition_to(JobStatus::Filled));
+        assert!(!JobStatus::Draft.can_transition_to(JobStatus::Filled));
+    }
+}
+
  ic_cdk::export_candid!();
\ No newline at end of file
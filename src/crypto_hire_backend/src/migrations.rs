@@ -0,0 +1,453 @@
+/* records in stable memory are wrapped in a small envelope that says which schema version they were written with:
+
+       [VERSION_MARKER, version, candid bytes...]
+
+   records written before the envelope existed are bare candid, which always starts with b"DIDL", and count as version 0.
+   the only such records are the jobs of the first release, see JobV0. reading a record decodes it with the schema of its
+   version and migrates it up to the current one, so old records are upgraded lazily as they are read and written back in
+   the current version the next time they are saved.
+
+   to change the schema of a stored type: copy its current definition here as `<Type>V<n>`, bump its version constant,
+   and add a `From<<Type>V<n>>` conversion plus a match arm in its `decode`. fields that are `Option`s can be added
+   without a new version, candid decodes them as None from older records */
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    milestones, next_application_id, save_application, Application, ApplicationStatus, Compensation, EmploymentType,
+    Escrow, Job, JobStatus, Memory, Milestone, ProfileDetails, WorkLocation, MEMORY_MANAGER,
+};
+
+const VERSION_MARKER: u8 = 0xFF;
+const LEGACY_VERSION: u8 = 0;
+
+pub(crate) fn encode<T: CandidType>(version: u8, value: &T) -> Vec<u8> {
+    let mut bytes = vec![VERSION_MARKER, version];
+    bytes.extend(Encode!(value).expect("failed to encode record"));
+    bytes
+}
+
+/* the schema version a record was written with, and its candid payload */
+pub(crate) fn split(bytes: &[u8]) -> (u8, &[u8]) {
+    match bytes {
+        [VERSION_MARKER, version, payload @ ..] => (*version, payload),
+        _ => (LEGACY_VERSION, bytes),
+    }
+}
+
+pub(crate) fn decode<T: CandidType + DeserializeOwned>(payload: &[u8]) -> T {
+    Decode!(payload, T).expect("failed to decode record")
+}
+
+pub(crate) fn unknown_version(record: &str, version: u8) -> ! {
+    ic_cdk::trap(&format!("{} record has schema version {}, which this canister does not know", record, version))
+}
+
+//Job as the first release stored it, as bare candid without an envelope. it had no employer or status, and
+//applicants were kept on the job by name
+#[derive(CandidType, Deserialize)]
+pub(crate) struct JobV0 {
+    id: u64,
+    title: String,
+    description: String,
+    created_at: u64,
+    applicant_name: Vec<String>,
+    accepted_applicants: Option<String>,
+}
+
+/* the employer of these jobs was never recorded. they are given to the management canister when read, and to the
+   controller that runs the upgrade by migrate_legacy_jobs. a job that had accepted someone is filled */
+impl From<JobV0> for JobV1 {
+    fn from(job: JobV0) -> Self {
+        JobV1 {
+            id: job.id,
+            title: job.title,
+            description: job.description,
+            employer: Principal::management_canister(),
+            status: if job.accepted_applicants.is_some() { JobStatus::Filled } else { JobStatus::Open },
+            created_at: job.created_at,
+            accepted_applicants: None,
+            escrow: None,
+            milestones: vec![],
+            review_window_secs: milestones::DEFAULT_REVIEW_WINDOW_SECS,
+            dispute: None,
+            disputes: vec![],
+        }
+    }
+}
+
+/* the applicants of a first release job as applications. their principals were never recorded, so they are
+   anonymous, and the accepted applicant is hired */
+fn legacy_applications(job: &JobV0) -> Vec<Application> {
+    let mut names = job.applicant_name.clone();
+    if let Some(accepted) = &job.accepted_applicants {
+        if !names.contains(accepted) {
+            names.push(accepted.clone());
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| Application {
+            id: 0,
+            job_id: job.id,
+            applicant: Principal::anonymous(),
+            status: if job.accepted_applicants.as_ref() == Some(&name) {
+                ApplicationStatus::Hired
+            } else {
+                ApplicationStatus::Submitted
+            },
+            display_name: name,
+            cover_letter: String::new(),
+            submitted_at: job.created_at,
+            profile: None,
+            attachments: vec![],
+            job_revision: 1,
+        })
+        .collect()
+}
+
+/* rewrites the first release jobs in the current version, turns their applicants into applications and gives
+   them to `employer`. it reads the job map as raw bytes, since a decoded job has lost its applicants, so it has to
+   run at the start of post_upgrade before anything else reads the jobs. returns how many jobs were migrated */
+pub(crate) fn migrate_legacy_jobs(employer: Principal) -> usize {
+    let raw: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))));
+    let legacy: Vec<(u64, Vec<u8>)> = raw.iter().filter(|(_, bytes)| split(bytes).0 == LEGACY_VERSION).collect();
+    let mut raw = raw;
+
+    for (id, bytes) in &legacy {
+        let legacy_job: JobV0 = decode(bytes);
+        for mut application in legacy_applications(&legacy_job) {
+            application.id = next_application_id();
+            save_application(&application);
+        }
+
+        let mut job: Job = JobV3::from(JobV2::from(JobV1::from(legacy_job))).into();
+        job.employer = employer;
+        raw.insert(*id, job.to_bytes().into_owned());
+    }
+    legacy.len()
+}
+
+//Job before it had posting details (compensation, location, employment type, skills, deadline and openings)
+#[derive(CandidType, Deserialize)]
+pub(crate) struct JobV1 {
+    id: u64,
+    title: String,
+    description: String,
+    employer: Principal,
+    status: JobStatus,
+    created_at: u64,
+    accepted_applicants: Option<Principal>,
+    escrow: Option<Escrow>,
+    milestones: Vec<Milestone>,
+    review_window_secs: u64,
+    dispute: Option<u64>,
+    disputes: Vec<u64>,
+}
+
+/* jobs posted before these details existed were bounty work, so they come across as remote contracts for one person */
+impl From<JobV1> for JobV2 {
+    fn from(job: JobV1) -> Self {
+        JobV2 {
+            id: job.id,
+            title: job.title,
+            description: job.description,
+            employer: job.employer,
+            status: job.status,
+            created_at: job.created_at,
+            compensation: None,
+            location: WorkLocation::Remote,
+            employment_type: EmploymentType::Contract,
+            skills: vec![],
+            deadline: None,
+            openings: 1,
+            accepted_applicants: job.accepted_applicants,
+            escrow: job.escrow,
+            milestones: job.milestones,
+            review_window_secs: job.review_window_secs,
+            dispute: job.dispute,
+            disputes: job.disputes,
+        }
+    }
+}
+
+//Job before it could hire more than one applicant
+#[derive(CandidType, Deserialize)]
+pub(crate) struct JobV2 {
+    id: u64,
+    title: String,
+    description: String,
+    employer: Principal,
+    status: JobStatus,
+    created_at: u64,
+    compensation: Option<Compensation>,
+    location: WorkLocation,
+    employment_type: EmploymentType,
+    skills: Vec<String>,
+    deadline: Option<u64>,
+    openings: u32,
+    accepted_applicants: Option<Principal>,
+    escrow: Option<Escrow>,
+    milestones: Vec<Milestone>,
+    review_window_secs: u64,
+    dispute: Option<u64>,
+    disputes: Vec<u64>,
+}
+
+impl From<JobV2> for JobV3 {
+    fn from(job: JobV2) -> Self {
+        JobV3 {
+            id: job.id,
+            title: job.title,
+            description: job.description,
+            employer: job.employer,
+            company: None,
+            status: job.status,
+            created_at: job.created_at,
+            compensation: job.compensation,
+            location: job.location,
+            employment_type: job.employment_type,
+            skills: job.skills,
+            deadline: job.deadline,
+            openings: job.openings,
+            accepted_applicants: job.accepted_applicants.into_iter().collect(),
+            escrow: job.escrow,
+            milestones: job.milestones,
+            review_window_secs: job.review_window_secs,
+            dispute: job.dispute,
+            disputes: job.disputes,
+        }
+    }
+}
+
+//Job before it could be edited
+#[derive(CandidType, Deserialize)]
+pub(crate) struct JobV3 {
+    id: u64,
+    title: String,
+    description: String,
+    employer: Principal,
+    company: Option<u64>,
+    status: JobStatus,
+    created_at: u64,
+    compensation: Option<Compensation>,
+    location: WorkLocation,
+    employment_type: EmploymentType,
+    skills: Vec<String>,
+    deadline: Option<u64>,
+    openings: u32,
+    accepted_applicants: Vec<Principal>,
+    escrow: Option<Escrow>,
+    milestones: Vec<Milestone>,
+    review_window_secs: u64,
+    dispute: Option<u64>,
+    disputes: Vec<u64>,
+}
+
+/* a job that was never edited is on its first revision */
+impl From<JobV3> for Job {
+    fn from(job: JobV3) -> Self {
+        Job {
+            id: job.id,
+            title: job.title,
+            description: job.description,
+            employer: job.employer,
+            company: job.company,
+            status: job.status,
+            created_at: job.created_at,
+            updated_at: None,
+            revision: 1,
+            compensation: job.compensation,
+            location: job.location,
+            employment_type: job.employment_type,
+            skills: job.skills,
+            deadline: job.deadline,
+            openings: job.openings,
+            max_applications: None,
+            accepted_applicants: job.accepted_applicants,
+            escrow: job.escrow,
+            milestones: job.milestones,
+            review_window_secs: job.review_window_secs,
+            dispute: job.dispute,
+            disputes: job.disputes,
+        }
+    }
+}
+
+//Application before it could carry attachments
+#[derive(CandidType, Deserialize)]
+pub(crate) struct ApplicationV1 {
+    id: u64,
+    job_id: u64,
+    applicant: Principal,
+    display_name: String,
+    cover_letter: String,
+    submitted_at: u64,
+    status: ApplicationStatus,
+    profile: Option<ProfileDetails>,
+}
+
+impl From<ApplicationV1> for ApplicationV2 {
+    fn from(application: ApplicationV1) -> Self {
+        ApplicationV2 {
+            id: application.id,
+            job_id: application.job_id,
+            applicant: application.applicant,
+            display_name: application.display_name,
+            cover_letter: application.cover_letter,
+            submitted_at: application.submitted_at,
+            status: application.status,
+            profile: application.profile,
+            attachments: vec![],
+        }
+    }
+}
+
+//Application before it recorded the revision of the job it was made against
+#[derive(CandidType, Deserialize)]
+pub(crate) struct ApplicationV2 {
+    id: u64,
+    job_id: u64,
+    applicant: Principal,
+    display_name: String,
+    cover_letter: String,
+    submitted_at: u64,
+    status: ApplicationStatus,
+    profile: Option<ProfileDetails>,
+    attachments: Vec<u64>,
+}
+
+/* jobs could not be edited before, so every earlier application was made against the first revision */
+impl From<ApplicationV2> for Application {
+    fn from(application: ApplicationV2) -> Self {
+        Application {
+            id: application.id,
+            job_id: application.job_id,
+            applicant: application.applicant,
+            display_name: application.display_name,
+            cover_letter: application.cover_letter,
+            submitted_at: application.submitted_at,
+            status: application.status,
+            profile: application.profile,
+            attachments: application.attachments,
+            job_revision: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::{get_job, job_applications};
+
+    fn baseline_job(id: u64, applicants: &[&str], accepted: Option<&str>) -> JobV0 {
+        JobV0 {
+            id,
+            title: String::from("Smart contract audit"),
+            description: String::from("Review the escrow canister"),
+            created_at: 1_700_000_000_000_000_000,
+            applicant_name: applicants.iter().map(|name| name.to_string()).collect(),
+            accepted_applicants: accepted.map(String::from),
+        }
+    }
+
+    /* the bytes the first release wrote for a job, bare candid */
+    fn baseline_bytes(job: &JobV0) -> Vec<u8> {
+        Encode!(job).unwrap()
+    }
+
+    #[test]
+    fn reads_baseline_jobs() {
+        let bytes = baseline_bytes(&baseline_job(7, &["alice", "bob"], None));
+        assert_eq!(&bytes[..4], b"DIDL");
+
+        let job = Job::from_bytes(Cow::Owned(bytes));
+        assert_eq!(job.id, 7);
+        assert_eq!(job.title, "Smart contract audit");
+        assert_eq!(job.employer, Principal::management_canister());
+        assert_eq!(job.status, JobStatus::Open);
+        assert_eq!(job.revision, 1);
+        assert!(job.accepted_applicants.is_empty());
+        assert!(job.escrow.is_none());
+        assert_eq!(job.review_window_secs, milestones::DEFAULT_REVIEW_WINDOW_SECS);
+    }
+
+    #[test]
+    fn baseline_job_with_an_accepted_applicant_is_filled() {
+        let job = Job::from_bytes(Cow::Owned(baseline_bytes(&baseline_job(1, &["alice"], Some("alice")))));
+        assert_eq!(job.status, JobStatus::Filled);
+    }
+
+    #[test]
+    fn migrated_jobs_are_written_in_the_current_version() {
+        let job = Job::from_bytes(Cow::Owned(baseline_bytes(&baseline_job(3, &[], None))));
+        let bytes = job.to_bytes().into_owned();
+        assert_ne!(split(&bytes).0, LEGACY_VERSION);
+
+        let reread = Job::from_bytes(Cow::Owned(bytes));
+        assert_eq!(reread.id, 3);
+        assert_eq!(reread.description, job.description);
+    }
+
+    #[test]
+    fn reads_every_application_version() {
+        let v1 = ApplicationV1 {
+            id: 4,
+            job_id: 2,
+            applicant: Principal::anonymous(),
+            display_name: String::from("alice"),
+            cover_letter: String::from("hello"),
+            submitted_at: 10,
+            status: ApplicationStatus::Shortlisted,
+            profile: None,
+        };
+        let application = Application::from_bytes(Cow::Owned(encode(1, &v1)));
+        assert_eq!((application.id, application.job_id), (4, 2));
+        assert_eq!(application.status, ApplicationStatus::Shortlisted);
+        assert!(application.attachments.is_empty());
+        assert_eq!(application.job_revision, 1);
+
+        let v2 = ApplicationV2::from(v1);
+        let application = Application::from_bytes(Cow::Owned(encode(2, &v2)));
+        assert_eq!(application.display_name, "alice");
+        assert_eq!(application.job_revision, 1);
+    }
+
+    #[test]
+    fn upgrade_migrates_baseline_jobs_and_their_applicants() {
+        let employer = Principal::from_slice(&[1; 29]);
+        {
+            let mut raw: StableBTreeMap<u64, Vec<u8>, Memory> =
+                StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))));
+            raw.insert(1, baseline_bytes(&baseline_job(1, &["alice", "bob"], Some("bob"))));
+            raw.insert(2, baseline_bytes(&baseline_job(2, &[], Some("carol"))));
+            raw.insert(3, baseline_bytes(&baseline_job(3, &["dave"], None)));
+        }
+
+        assert_eq!(migrate_legacy_jobs(employer), 3);
+        assert_eq!(migrate_legacy_jobs(employer), 0);
+
+        let job = get_job(1).unwrap();
+        assert_eq!(job.employer, employer);
+        assert_eq!(job.status, JobStatus::Filled);
+        let applications = job_applications(1);
+        let statuses: Vec<(&str, ApplicationStatus)> =
+            applications.iter().map(|a| (a.display_name.as_str(), a.status)).collect();
+        assert_eq!(statuses, vec![("alice", ApplicationStatus::Submitted), ("bob", ApplicationStatus::Hired)]);
+        assert!(applications.iter().all(|a| a.submitted_at == job.created_at));
+
+        let hired = job_applications(2);
+        assert_eq!(hired.len(), 1);
+        assert_eq!((hired[0].display_name.as_str(), hired[0].status), ("carol", ApplicationStatus::Hired));
+
+        assert_eq!(get_job(3).unwrap().status, JobStatus::Open);
+        let ids: Vec<u64> = (1..=3).flat_map(job_applications).map(|a| a.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+}
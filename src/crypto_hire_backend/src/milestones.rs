@@ -0,0 +1,321 @@
+/* milestones split a job's bounty into tranches that are paid out as the hired applicant delivers them */
+use std::cell::RefCell;
+use std::time::Duration;
+
+use candid::CandidType;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+
+use crate::audit::{self, Operation};
+use crate::{
+    ensure_employer, get_job, ledger, lock_escrow, save_job, settle_escrow, validate_text, Error, EscrowStatus,
+    Job, JobStatus, Memory, MEMORY_MANAGER,
+};
+use crate::rate_limit::throttle;
+
+/* how long the employer has to review a delivered milestone before it is approved for them */
+pub(crate) const DEFAULT_REVIEW_WINDOW_SECS: u64 = 7 * 24 * 60 * 60;
+const MIN_REVIEW_WINDOW_SECS: u64 = 60;
+const MAX_REVIEW_WINDOW_SECS: u64 = 90 * 24 * 60 * 60;
+
+/* how long to wait before trying an automatic approval again when the payout failed */
+const RETRY_SECS: u64 = 60;
+/* how many times a failed automatic approval is tried again before it is left to the employer */
+const MAX_RETRIES: u32 = 60;
+
+const MAX_MILESTONES_PER_JOB: usize = 20;
+const MAX_MILESTONE_DESCRIPTION_LEN: usize = 1_000;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+//a tranche of a job's bounty
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Milestone {
+    pub(crate) id: u32,
+    pub(crate) description: String,
+    pub(crate) amount: u128, // paid out of the job's escrow when the milestone is approved
+    pub(crate) status: MilestoneStatus,
+    pub(crate) delivered_at: Option<u64>,
+    pub(crate) released_at: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum MilestoneStatus {
+    Pending,   // waiting for the hired applicant to deliver
+    Delivered, // waiting for the employer to approve
+    Released,  // paid out
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CreateMilestone {
+    description: String,
+    amount: u128,
+}
+
+thread_local! {
+    /*delivered milestones waiting for review, keyed by (job_id, milestone_id) with the time they are approved automatically.
+      kept in stable memory so the review timers can be set again after an upgrade*/
+    static PENDING_REVIEWS: RefCell<StableBTreeMap<(u64, u32), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+        ));
+}
+
+fn milestone_not_found(milestone_id: u32) -> Error {
+    Error::NotFound { resource: String::from("milestone"), id: milestone_id.to_string() }
+}
+
+fn find_milestone(job: &mut Job, milestone_id: u32) -> Result<&mut Milestone, Error> {
+    job.milestones
+        .iter_mut()
+        .find(|m| m.id == milestone_id)
+        .ok_or(milestone_not_found(milestone_id))
+}
+
+fn ensure_milestone_status(milestone: &Milestone, status: MilestoneStatus, action: &str) -> Result<(), Error> {
+    if milestone.status == status {
+        Ok(())
+    } else {
+        Err(Error::InvalidState {
+            current: format!("milestone {:?}", milestone.status),
+            action: action.to_string(),
+        })
+    }
+}
+
+/* called when the rest of the escrow is paid out in one go, every milestone counts as paid */
+pub(crate) fn release_all(job: &mut Job) {
+    for milestone in job.milestones.iter_mut().filter(|m| m.status != MilestoneStatus::Released) {
+        milestone.status = MilestoneStatus::Released;
+        milestone.released_at = Some(time());
+        PENDING_REVIEWS.with(|reviews| reviews.borrow_mut().remove(&(job.id, milestone.id)));
+    }
+}
+
+/* splits off part of the job's bounty as a milestone, milestones can never add up to more than the bounty.
+   a milestone has to be worth more than the ledger fee, or paying it out would send nothing */
+#[ic_cdk::update(guard = "throttle")]
+async fn add_milestone(job_id: u64, milestone: CreateMilestone) -> Result<Milestone, Error> {
+    let job = get_job(job_id)?;
+    ensure_employer(&job, "add a milestone")?;
+    let fee = match job.escrow {
+        Some(escrow) => ledger::fee(escrow.ledger).await?,
+        None => 0,
+    };
+
+    // the job may have changed while the fee was looked up
+    let mut job = get_job(job_id)?;
+    job.ensure_status(&[JobStatus::Draft, JobStatus::Open, JobStatus::Filled], "add a milestone")?;
+    job.ensure_escrow_idle()?;
+    validate_text("description", &milestone.description, true, MAX_MILESTONE_DESCRIPTION_LEN)?;
+
+    if milestone.amount <= fee {
+        return Err(Error::validation("amount", &format!("must be more than the ledger fee of {}", fee)));
+    }
+    let escrow = job
+        .escrow
+        .as_ref()
+        .ok_or(Error::validation("amount", "the job has no bounty to split into milestones"))?;
+    if matches!(escrow.status, EscrowStatus::Released | EscrowStatus::Refunded) {
+        return Err(Error::InvalidState {
+            current: format!("escrow {:?}", escrow.status),
+            action: String::from("add a milestone"),
+        });
+    }
+    if job.milestones.len() >= MAX_MILESTONES_PER_JOB {
+        return Err(Error::CapacityExceeded {
+            resource: String::from("milestones"),
+            limit: MAX_MILESTONES_PER_JOB as u64,
+        });
+    }
+
+    let committed = job.milestones.iter().fold(0u128, |sum, m| sum.saturating_add(m.amount));
+    let remaining = escrow.amount.saturating_sub(committed);
+    if milestone.amount > remaining {
+        return Err(Error::validation(
+            "amount",
+            &format!("only {} of the bounty is left to assign", remaining),
+        ));
+    }
+
+    let milestone = Milestone {
+        id: job.milestones.len() as u32 + 1,
+        description: milestone.description,
+        amount: milestone.amount,
+        status: MilestoneStatus::Pending,
+        delivered_at: None,
+        released_at: None,
+    };
+    job.milestones.push(milestone.clone());
+    save_job(&job);
+    audit::record(Operation::MilestoneAdded, Some(job_id), Some(milestone.id as u64), None);
+
+    Ok(milestone)
+}
+
+/* how long the employer gets to review each delivered milestone, applies to milestones delivered from now on */
+#[ic_cdk::update(guard = "throttle")]
+fn set_review_window(job_id: u64, seconds: u64) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    ensure_employer(&job, "set the review window")?;
+
+    if !(MIN_REVIEW_WINDOW_SECS..=MAX_REVIEW_WINDOW_SECS).contains(&seconds) {
+        return Err(Error::validation(
+            "seconds",
+            &format!("must be between {} and {}", MIN_REVIEW_WINDOW_SECS, MAX_REVIEW_WINDOW_SECS),
+        ));
+    }
+
+    job.review_window_secs = seconds;
+    save_job(&job);
+    audit::record(Operation::ReviewWindowChanged, Some(job_id), None, None);
+    Ok(())
+}
+
+/* the hired applicant hands in a milestone. if the employer does not approve it within the
+   review window it is approved automatically */
+#[ic_cdk::update(guard = "throttle")]
+fn deliver_milestone(job_id: u64, milestone_id: u32) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    if job.worker() != Some(caller()) {
+        return Err(Error::unauthorized("deliver a milestone"));
+    }
+    job.ensure_status(&[JobStatus::Filled], "deliver a milestone")?;
+
+    let window = job.review_window_secs;
+    let milestone = find_milestone(&mut job, milestone_id)?;
+    ensure_milestone_status(milestone, MilestoneStatus::Pending, "deliver the milestone")?;
+    milestone.status = MilestoneStatus::Delivered;
+    milestone.delivered_at = Some(time());
+    save_job(&job);
+    audit::record(Operation::MilestoneDelivered, Some(job_id), Some(milestone_id as u64), None);
+
+    let due = time().saturating_add(window.saturating_mul(NANOS_PER_SEC));
+    PENDING_REVIEWS.with(|reviews| reviews.borrow_mut().insert((job_id, milestone_id), due));
+    schedule_review(job_id, milestone_id, Duration::from_secs(window), 0);
+
+    Ok(())
+}
+
+/* the employer accepts a delivered milestone and its tranche is paid to the hired applicant */
+#[ic_cdk::update(guard = "throttle")]
+async fn approve_milestone(job_id: u64, milestone_id: u32) -> Result<(), Error> {
+    let job = get_job(job_id)?;
+    ensure_employer(&job, "approve a milestone")?;
+
+    release_milestone(job_id, milestone_id).await
+}
+
+async fn release_milestone(job_id: u64, milestone_id: u32) -> Result<(), Error> {
+    let mut job = get_job(job_id)?;
+    job.ensure_status(&[JobStatus::Filled], "release a milestone")?;
+    job.ensure_escrow_idle()?;
+
+    let worker = job.worker().ok_or(Error::InvalidState {
+        current: format!("{:?}", job.status),
+        action: String::from("release a milestone"),
+    })?;
+    let milestone = find_milestone(&mut job, milestone_id)?;
+    ensure_milestone_status(milestone, MilestoneStatus::Delivered, "release the milestone")?;
+    let amount = milestone.amount;
+
+    if job.escrow.as_ref().map(|e| e.status) != Some(EscrowStatus::Held) {
+        return Err(Error::InvalidState {
+            current: String::from("escrow not held"),
+            action: String::from("release a milestone"),
+        });
+    }
+
+    let (ledger_id, _) = lock_escrow(&mut job, EscrowStatus::Releasing);
+    let result = ledger::payout(ledger_id, worker, amount, job_id).await;
+    let mut job = settle_escrow(job_id, result, EscrowStatus::Held, EscrowStatus::Held)?;
+
+    if let Some(milestone) = job.milestones.iter_mut().find(|m| m.id == milestone_id) {
+        milestone.status = MilestoneStatus::Released;
+        milestone.released_at = Some(time());
+    }
+    if let Some(escrow) = job.escrow.as_mut() {
+        escrow.released = escrow.released.saturating_add(amount);
+        if escrow.released >= escrow.amount {
+            escrow.status = EscrowStatus::Released;
+        }
+    }
+    save_job(&job);
+    PENDING_REVIEWS.with(|reviews| reviews.borrow_mut().remove(&(job_id, milestone_id)));
+    audit::record(Operation::MilestoneReleased, Some(job_id), Some(milestone_id as u64), Some(worker));
+
+    Ok(())
+}
+
+fn schedule_review(job_id: u64, milestone_id: u32, delay: Duration, attempt: u32) {
+    ic_cdk_timers::set_timer(delay, move || ic_cdk::spawn(auto_approve(job_id, milestone_id, attempt)));
+}
+
+/* whether an automatic approval that failed with `err` is worth trying again. a failed or rejected transfer, or
+   an escrow that is busy with another payout, can clear up, anything else will fail the same way every time */
+fn should_retry(err: &Error, attempt: u32) -> bool {
+    attempt < MAX_RETRIES && matches!(err, Error::TransferFailed { .. } | Error::InvalidState { .. })
+}
+
+/* runs when a review window ends. a payout that fails is tried again later, as long as the
+   milestone is still waiting for it and no dispute has taken over the escrow. after MAX_RETRIES
+   it is left for the employer to approve, which shows up in the job's history */
+async fn auto_approve(job_id: u64, milestone_id: u32, attempt: u32) {
+    let Some(due) = PENDING_REVIEWS.with(|reviews| reviews.borrow().get(&(job_id, milestone_id))) else {
+        return; // approved by the employer in the meantime
+    };
+    if due > time() {
+        schedule_review(job_id, milestone_id, Duration::from_nanos(due - time()), attempt);
+        return;
+    }
+
+    if let Err(err) = release_milestone(job_id, milestone_id).await {
+        let still_waiting = get_job(job_id).is_ok_and(|job| {
+            job.status == JobStatus::Filled
+                && job.dispute.is_none()
+                && job
+                    .milestones
+                    .iter()
+                    .any(|m| m.id == milestone_id && m.status == MilestoneStatus::Delivered)
+        });
+
+        if still_waiting && should_retry(&err, attempt) {
+            schedule_review(job_id, milestone_id, Duration::from_secs(RETRY_SECS), attempt + 1);
+        } else {
+            if still_waiting {
+                audit::record(Operation::AutoApprovalAbandoned, Some(job_id), Some(milestone_id as u64), None);
+            }
+            PENDING_REVIEWS.with(|reviews| reviews.borrow_mut().remove(&(job_id, milestone_id)));
+        }
+    }
+}
+
+/* timers do not survive an upgrade, so every pending review gets its timer back */
+pub(crate) fn rearm_review_timers() {
+    let now = time();
+    PENDING_REVIEWS.with(|reviews| {
+        for ((job_id, milestone_id), due) in reviews.borrow().iter() {
+            schedule_review(job_id, milestone_id, Duration::from_nanos(due.saturating_sub(now)), 0);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_transient_failures_until_the_cap() {
+        let failed = Error::TransferFailed { message: String::from("TemporarilyUnavailable") };
+        assert!(should_retry(&failed, 0));
+        assert!(should_retry(&failed, MAX_RETRIES - 1));
+        assert!(!should_retry(&failed, MAX_RETRIES));
+    }
+
+    #[test]
+    fn does_not_retry_failures_that_cannot_clear_up() {
+        assert!(!should_retry(&milestone_not_found(1), 0));
+        assert!(!should_retry(&Error::validation("amount", "must be more than the ledger fee of 10"), 0));
+    }
+}
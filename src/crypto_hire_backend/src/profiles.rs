@@ -0,0 +1,162 @@
+/* applicant profiles, so applicants do not have to retype who they are for every application.
+   an application keeps a copy of the profile as it was when it was submitted */
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+
+use crate::audit::{self, Operation};
+use crate::{migrations, normalize_skills, validate_text, validate_url, Error, Memory, MAX_DISPLAY_NAME_LEN, MEMORY_MANAGER};
+use crate::rate_limit::throttle;
+
+const MAX_HEADLINE_LEN: usize = 200;
+const MAX_EXPERIENCE: usize = 20;
+const MAX_EXPERIENCE_TITLE_LEN: usize = 100;
+const MAX_ORGANIZATION_LEN: usize = 100;
+const MAX_EXPERIENCE_DESCRIPTION_LEN: usize = 2_000;
+const MAX_LINKS: usize = 10;
+const MAX_LINK_LEN: usize = 200;
+const MAX_RESUME_LEN: usize = 200;
+
+//the schema version new profiles are written with, see migrations.rs
+const PROFILE_VERSION: u8 = 1;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApplicantProfile {
+    owner: Principal,
+    details: ProfileDetails,
+    created_at: u64,
+    updated_at: u64,
+}
+
+//the part of a profile the applicant fills in, and what an application keeps a copy of
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ProfileDetails {
+    pub(crate) display_name: String,
+    headline: String,
+    skills: Vec<String>,        // lowercase tags, like the skills of a job
+    experience: Vec<Experience>,
+    links: Vec<String>,         // portfolio, code hosting, social profiles
+    resume: Option<String>,     // where the resume can be found, a link or the hash of a document kept elsewhere
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Experience {
+    title: String,
+    organization: String,
+    started_at: u64,
+    ended_at: Option<u64>, // None while it is the current position
+    description: String,
+}
+
+impl Storable for ApplicantProfile {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(PROFILE_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("ApplicantProfile", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static PROFILES: RefCell<StableBTreeMap<Principal, ApplicantProfile, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        ));
+}
+
+fn profile_not_found(owner: Principal) -> Error {
+    Error::NotFound { resource: String::from("profile"), id: owner.to_text() }
+}
+
+/* the profile details of a principal, if they have a profile */
+pub(crate) fn profile_details(owner: Principal) -> Option<ProfileDetails> {
+    PROFILES.with(|profiles| profiles.borrow().get(&owner)).map(|profile| profile.details)
+}
+
+/* checks the details and returns them with their skills normalized */
+fn validate_details(details: ProfileDetails) -> Result<ProfileDetails, Error> {
+    validate_text("display_name", &details.display_name, true, MAX_DISPLAY_NAME_LEN)?;
+    validate_text("headline", &details.headline, false, MAX_HEADLINE_LEN)?;
+
+    if details.experience.len() > MAX_EXPERIENCE {
+        return Err(Error::CapacityExceeded { resource: String::from("experience"), limit: MAX_EXPERIENCE as u64 });
+    }
+    for experience in &details.experience {
+        validate_text("experience.title", &experience.title, true, MAX_EXPERIENCE_TITLE_LEN)?;
+        validate_text("experience.organization", &experience.organization, false, MAX_ORGANIZATION_LEN)?;
+        validate_text("experience.description", &experience.description, false, MAX_EXPERIENCE_DESCRIPTION_LEN)?;
+        if experience.ended_at.is_some_and(|ended_at| ended_at < experience.started_at) {
+            return Err(Error::validation("experience.ended_at", "must not be before started_at"));
+        }
+    }
+
+    if details.links.len() > MAX_LINKS {
+        return Err(Error::CapacityExceeded { resource: String::from("links"), limit: MAX_LINKS as u64 });
+    }
+    for link in &details.links {
+        validate_url("links", link, MAX_LINK_LEN)?;
+    }
+    if let Some(resume) = &details.resume {
+        validate_text("resume", resume, true, MAX_RESUME_LEN)?;
+    }
+
+    Ok(ProfileDetails { skills: normalize_skills(details.skills)?, ..details })
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn create_profile(details: ProfileDetails) -> Result<ApplicantProfile, Error> {
+    let owner = caller();
+    if PROFILES.with(|profiles| profiles.borrow().contains_key(&owner)) {
+        return Err(Error::InvalidState { current: String::from("profile exists"), action: String::from("create a profile") });
+    }
+
+    let profile = ApplicantProfile { owner, details: validate_details(details)?, created_at: time(), updated_at: time() };
+    PROFILES.with(|profiles| profiles.borrow_mut().insert(owner, profile.clone()));
+    audit::record(Operation::ProfileCreated, None, None, None);
+    Ok(profile)
+}
+
+/* replaces the caller's profile details. applications already submitted keep the details they were sent with */
+#[ic_cdk::update(guard = "throttle")]
+fn update_profile(details: ProfileDetails) -> Result<ApplicantProfile, Error> {
+    let owner = caller();
+    let mut profile = PROFILES
+        .with(|profiles| profiles.borrow().get(&owner))
+        .ok_or(profile_not_found(owner))?;
+
+    profile.details = validate_details(details)?;
+    profile.updated_at = time();
+    PROFILES.with(|profiles| profiles.borrow_mut().insert(owner, profile.clone()));
+    audit::record(Operation::ProfileUpdated, None, None, None);
+    Ok(profile)
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn delete_profile() -> Result<(), Error> {
+    let owner = caller();
+    PROFILES
+        .with(|profiles| profiles.borrow_mut().remove(&owner))
+        .ok_or(profile_not_found(owner))?;
+    audit::record(Operation::ProfileDeleted, None, None, None);
+    Ok(())
+}
+
+/* the caller's own profile */
+#[ic_cdk::query]
+fn fetch_profile() -> Result<ApplicantProfile, Error> {
+    let owner = caller();
+    PROFILES
+        .with(|profiles| profiles.borrow().get(&owner))
+        .ok_or(profile_not_found(owner))
+}
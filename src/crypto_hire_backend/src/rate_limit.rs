@@ -0,0 +1,272 @@
+/* per-principal rate limits, so nobody can burn the canister's cycles or fill its storage by calling it in a loop.
+
+   every update call goes through the `throttle` guard, which rejects the anonymous principal and counts the call
+   against the caller's budget of updates in a sliding window. create_job and apply_to_job also have budgets of
+   their own, which they check themselves. only those two return Error::RateLimited: a guard can only reject with
+   a message, so a call over the update budget is rejected before it runs. the calls in the window are kept on the
+   heap, so they start over after an upgrade, while the limits themselves are kept in stable memory. controllers
+   are never limited.
+
+   inspect_message drops ingress that would be rejected anyway before it is executed and paid for */
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::{accept_message, arg_data_raw_size, method_name};
+use ic_cdk::api::{caller, is_controller, time};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+
+use crate::audit::{self, Operation};
+use crate::blobs::MAX_CHUNK_SIZE;
+use crate::config::ensure_admin;
+use crate::{migrations, Error, Memory, MEMORY_MANAGER};
+
+//the schema version the limits are written with, see migrations.rs
+const RATE_LIMITS_VERSION: u8 = 1;
+
+/* the largest argument an ingress message may carry, put_chunk aside. the biggest inputs, a profile
+   or a job description, are well under this */
+const MAX_INGRESS_BYTES: usize = 64 * 1024;
+
+/* room for the candid encoding around a chunk */
+const CHUNK_OVERHEAD_BYTES: usize = 1024;
+
+/* once this many principals have calls on record, the ones whose window has passed are dropped */
+const MAX_TRACKED_PRINCIPALS: usize = 10_000;
+
+const MAX_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/* every update method, ingress to any other method is dropped. keep this in step with the .did file */
+const UPDATE_METHODS: [&str; 42] = [
+    "create_job",
+    "update_job",
+    "apply_to_job",
+    "withdraw_application",
+    "publish_job",
+    "close_job",
+    "cancel_job",
+    "accept_job",
+    "complete_job",
+    "add_milestone",
+    "set_review_window",
+    "set_deadline",
+    "create_company",
+    "update_company",
+    "add_company_owner",
+    "remove_company_owner",
+    "set_company_verified",
+    "create_profile",
+    "update_profile",
+    "delete_profile",
+    "begin_upload",
+    "put_chunk",
+    "commit_upload",
+    "cancel_upload",
+    "delete_blob",
+    "deliver_milestone",
+    "approve_milestone",
+    "open_dispute",
+    "submit_evidence",
+    "rule_on_dispute",
+    "execute_ruling",
+    "break_deadlock",
+    "add_arbiter",
+    "remove_arbiter",
+    "update_application_status",
+    "set_ledger",
+    "set_rate_limits",
+    "set_config",
+    "add_admin",
+    "remove_admin",
+    "add_moderator",
+    "remove_moderator",
+];
+
+//how many calls a principal may make within window_secs
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RateLimits {
+    window_secs: u64,
+    max_updates: u32,                // update calls of any kind
+    max_jobs_created: u32,           // create_job calls
+    max_applications_submitted: u32, // apply_to_job calls
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits { window_secs: 60, max_updates: 30, max_jobs_created: 5, max_applications_submitted: 10 }
+    }
+}
+
+impl Storable for RateLimits {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(RATE_LIMITS_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("RateLimits", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+//the budgets a call can be counted against
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Budget {
+    Updates,
+    CreateJob,
+    ApplyToJob,
+}
+
+impl RateLimits {
+    fn max_calls(&self, budget: Budget) -> usize {
+        match budget {
+            Budget::Updates => self.max_updates as usize,
+            Budget::CreateJob => self.max_jobs_created as usize,
+            Budget::ApplyToJob => self.max_applications_submitted as usize,
+        }
+    }
+
+    fn window_nanos(&self) -> u64 {
+        self.window_secs * 1_000_000_000
+    }
+}
+
+thread_local! {
+    /*the limits set by an admin*/
+    static LIMITS: RefCell<Cell<RateLimits, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))), RateLimits::default())
+            .expect("cannot create rate limits")
+    );
+
+    /*the times of the calls each principal made within the window, oldest first*/
+    static CALLS: RefCell<HashMap<(Principal, Budget), VecDeque<u64>>> = RefCell::new(HashMap::new());
+}
+
+fn limits() -> RateLimits {
+    LIMITS.with(|limits| limits.borrow().get().clone())
+}
+
+/* how long until the principal may call again, or None if the budget has room for another call */
+fn wait_time(calls: &mut VecDeque<u64>, limits: &RateLimits, budget: Budget, now: u64) -> Option<u64> {
+    let window = limits.window_nanos();
+    while calls.front().is_some_and(|&call| call.saturating_add(window) <= now) {
+        calls.pop_front();
+    }
+    if calls.len() < limits.max_calls(budget) {
+        return None;
+    }
+    calls.front().map(|&oldest| oldest.saturating_add(window) - now)
+}
+
+/* counts a call by the caller against a budget, rejecting it when the budget is used up */
+pub(crate) fn take(budget: Budget) -> Result<(), Error> {
+    let principal = caller();
+    if is_controller(&principal) {
+        return Ok(());
+    }
+
+    let now = time();
+    let limits = limits();
+    CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        if calls.len() >= MAX_TRACKED_PRINCIPALS {
+            let window = limits.window_nanos();
+            calls.retain(|_, times| times.back().is_some_and(|&last| last.saturating_add(window) > now));
+        }
+
+        let times = calls.entry((principal, budget)).or_default();
+        match wait_time(times, &limits, budget, now) {
+            Some(wait) => Err(Error::RateLimited { retry_after_secs: wait.div_ceil(1_000_000_000) }),
+            None => {
+                times.push_back(now);
+                Ok(())
+            }
+        }
+    })
+}
+
+/* the guard on every update method. it rejects the call outright, so the caller sees a reject message
+   rather than Error::RateLimited */
+pub(crate) fn throttle() -> Result<(), String> {
+    if caller() == Principal::anonymous() {
+        return Err(String::from("the anonymous principal cannot make update calls"));
+    }
+    take(Budget::Updates).map_err(|error| match error {
+        Error::RateLimited { retry_after_secs } => {
+            format!("too many update calls, try again in {} seconds", retry_after_secs)
+        }
+        error => format!("{:?}", error),
+    })
+}
+
+/* runs on a single replica before ingress is executed. state changes made here are thrown away,
+   so this only turns away what the guard would reject anyway: it is not a replacement for it */
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = method_name();
+    let max_size = match method.as_str() {
+        "put_chunk" => MAX_CHUNK_SIZE + CHUNK_OVERHEAD_BYTES,
+        name if UPDATE_METHODS.contains(&name) => MAX_INGRESS_BYTES,
+        _ => return,
+    };
+    if arg_data_raw_size() > max_size {
+        return;
+    }
+
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return;
+    }
+    if !is_controller(&principal) {
+        let limits = limits();
+        let limited = CALLS.with(|calls| {
+            let mut calls = calls.borrow_mut();
+            let times = calls.get_mut(&(principal, Budget::Updates));
+            times.is_some_and(|times| wait_time(times, &limits, Budget::Updates, time()).is_some())
+        });
+        if limited {
+            return;
+        }
+    }
+
+    accept_message();
+}
+
+fn validate_limits(limits: &RateLimits) -> Result<(), Error> {
+    if !(1..=MAX_WINDOW_SECS).contains(&limits.window_secs) {
+        return Err(Error::validation("window_secs", &format!("must be between 1 and {}", MAX_WINDOW_SECS)));
+    }
+    for (field, max) in [
+        ("max_updates", limits.max_updates),
+        ("max_jobs_created", limits.max_jobs_created),
+        ("max_applications_submitted", limits.max_applications_submitted),
+    ] {
+        if max == 0 {
+            return Err(Error::validation(field, "must be greater than zero"));
+        }
+    }
+    Ok(())
+}
+
+#[ic_cdk::update(guard = "throttle")]
+fn set_rate_limits(limits: RateLimits) -> Result<(), Error> {
+    ensure_admin("set the rate limits")?;
+    validate_limits(&limits)?;
+
+    LIMITS
+        .with(|cell| cell.borrow_mut().set(limits))
+        .expect("cannot set rate limits");
+    audit::record(Operation::RateLimitsChanged, None, None, None);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_rate_limits() -> RateLimits {
+    limits()
+}
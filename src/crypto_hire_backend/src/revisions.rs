@@ -0,0 +1,257 @@
+/* editing a posting after it was created. every edit bumps the job's revision and keeps the posting as it was
+   before, so earlier revisions can still be read and an applicant can see what the job said when they applied */
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+
+use crate::audit::{self, Operation};
+use crate::{
+    config, ensure_employer, get_job, job_applications, migrations, normalize_skills, save_job, search, validate_compensation, validate_max_applications, validate_text,
+    Compensation, EmploymentType, Error, Job, JobStatus, Memory, WorkLocation, MAX_OPENINGS, MEMORY_MANAGER,
+};
+use crate::rate_limit::throttle;
+
+/* the most times a job can be edited */
+const MAX_REVISIONS_PER_JOB: u32 = 100;
+
+//the schema version new revisions are written with, see migrations.rs
+const REVISION_VERSION: u8 = 1;
+
+//the changes to a job, fields left out stay as they are. the deadline is moved with set_deadline
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct UpdateJob {
+    title: Option<String>,
+    description: Option<String>,
+    compensation: Option<Option<Compensation>>, // Some(None) takes the pay range off the job
+    location: Option<WorkLocation>,
+    employment_type: Option<EmploymentType>,
+    skills: Option<Vec<String>>,
+    openings: Option<u32>,
+    max_applications: Option<u32>,
+}
+
+//the posting of a job as it was at one revision
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct JobRevision {
+    job_id: u64,
+    revision: u32,
+    created_at: u64,    // when the job was created or edited into this revision
+    author: Principal,  // the employer that wrote it
+    title: String,
+    description: String,
+    compensation: Option<Compensation>,
+    location: WorkLocation,
+    employment_type: EmploymentType,
+    skills: Vec<String>,
+    openings: u32,
+}
+
+impl JobRevision {
+    fn of(job: &Job) -> Self {
+        JobRevision {
+            job_id: job.id,
+            revision: job.revision,
+            created_at: job.updated_at.unwrap_or(job.created_at),
+            author: job.employer,
+            title: job.title.clone(),
+            description: job.description.clone(),
+            compensation: job.compensation.clone(),
+            location: job.location,
+            employment_type: job.employment_type,
+            skills: job.skills.clone(),
+            openings: job.openings,
+        }
+    }
+}
+
+impl Storable for JobRevision {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(migrations::encode(REVISION_VERSION, self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        match migrations::split(&bytes) {
+            (1, payload) => migrations::decode(payload),
+            (version, _) => migrations::unknown_version("JobRevision", version),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    /*the revisions a job was edited away from, keyed by (job_id, revision). the current revision is the job itself*/
+    static REVISIONS: RefCell<StableBTreeMap<(u64, u32), JobRevision, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+        ));
+}
+
+/* edits a draft, open or closed job. a closed job can be opened again with publish_job once it is fixed */
+#[ic_cdk::update(guard = "throttle")]
+fn update_job(job_id: u64, update: UpdateJob) -> Result<Job, Error> {
+    let mut job = get_job(job_id)?;
+    ensure_employer(&job, "edit the job")?;
+    job.ensure_status(&[JobStatus::Draft, JobStatus::Open, JobStatus::Closed], "edit the job")?;
+    job.ensure_escrow_idle()?;
+    if job.revision >= MAX_REVISIONS_PER_JOB {
+        return Err(Error::CapacityExceeded { resource: String::from("revisions"), limit: MAX_REVISIONS_PER_JOB as u64 });
+    }
+
+    let previous = JobRevision::of(&job);
+    if !apply_update(&mut job, update)? {
+        return Err(Error::validation("update", "does not change anything"));
+    }
+
+    REVISIONS.with(|revisions| revisions.borrow_mut().insert((job_id, previous.revision), previous));
+    job.revision += 1;
+    job.updated_at = Some(time());
+    save_job(&job);
+    search::index_job(&job);
+    audit::record(Operation::JobUpdated, Some(job_id), None, None);
+    Ok(job)
+}
+
+/* makes the changes of an update to a job, and returns whether anything changed */
+fn apply_update(job: &mut Job, update: UpdateJob) -> Result<bool, Error> {
+    let limits = config::size_limits();
+    let mut changed = false;
+
+    if let Some(title) = update.title {
+        validate_text("title", &title, true, limits.max_title_len as usize)?;
+        changed |= title != job.title;
+        job.title = title;
+    }
+    if let Some(description) = update.description {
+        validate_text("description", &description, false, limits.max_description_len as usize)?;
+        changed |= description != job.description;
+        job.description = description;
+    }
+    if let Some(compensation) = update.compensation {
+        if let Some(compensation) = &compensation {
+            validate_compensation(compensation)?;
+        }
+        changed |= compensation != job.compensation;
+        job.compensation = compensation;
+    }
+    if let Some(location) = update.location {
+        changed |= location != job.location;
+        job.location = location;
+    }
+    if let Some(employment_type) = update.employment_type {
+        changed |= employment_type != job.employment_type;
+        job.employment_type = employment_type;
+    }
+    if let Some(skills) = update.skills {
+        let skills = normalize_skills(skills)?;
+        changed |= skills != job.skills;
+        job.skills = skills;
+    }
+    if let Some(openings) = update.openings {
+        if !(1..=MAX_OPENINGS).contains(&openings) {
+            return Err(Error::validation("openings", &format!("must be between 1 and {}", MAX_OPENINGS)));
+        }
+        if job.escrow.is_some() && openings > 1 {
+            return Err(Error::validation("openings", "a job with a bounty hires a single applicant"));
+        }
+        if (openings as usize) <= job.accepted_applicants.len() {
+            return Err(Error::validation(
+                "openings",
+                &format!("must be more than the {} applicants already hired", job.accepted_applicants.len()),
+            ));
+        }
+        changed |= openings != job.openings;
+        job.openings = openings;
+    }
+    if let Some(max_applications) = update.max_applications {
+        validate_max_applications(Some(max_applications))?;
+        let received = job_applications(job.id).len();
+        if (max_applications as usize) <= received && job.status == JobStatus::Open {
+            return Err(Error::validation(
+                "max_applications",
+                &format!("must be more than the {} applications the open job already has", received),
+            ));
+        }
+        changed |= job.max_applications != Some(max_applications);
+        job.max_applications = Some(max_applications);
+    }
+
+    Ok(changed)
+}
+
+/* a job as it was at one revision, the current one included */
+#[ic_cdk::query]
+fn fetch_job_revision(job_id: u64, revision: u32) -> Result<JobRevision, Error> {
+    let job = get_job(job_id)?;
+    if revision == job.revision {
+        return Ok(JobRevision::of(&job));
+    }
+
+    REVISIONS
+        .with(|revisions| revisions.borrow().get(&(job_id, revision)))
+        .ok_or(Error::NotFound { resource: String::from("revision"), id: format!("{}/{}", job_id, revision) })
+}
+
+/* every revision of a job, oldest first */
+#[ic_cdk::query]
+fn list_job_revisions(job_id: u64) -> Result<Vec<JobRevision>, Error> {
+    let job = get_job(job_id)?;
+    let mut history: Vec<JobRevision> = REVISIONS.with(|revisions| {
+        revisions
+            .borrow()
+            .range((job_id, 0)..=(job_id, u32::MAX))
+            .map(|(_, revision)| revision)
+            .collect()
+    });
+    history.push(JobRevision::of(&job));
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_job;
+
+    fn pay(min: u128, max: u128) -> Compensation {
+        Compensation { min, max, currency: String::from("USD") }
+    }
+
+    #[test]
+    fn the_same_pay_range_is_not_a_change() {
+        let mut job = sample_job(1, "Rust developer");
+        job.compensation = Some(pay(100, 200));
+
+        let update = UpdateJob { compensation: Some(Some(pay(100, 200))), ..Default::default() };
+        assert!(!apply_update(&mut job, update).unwrap());
+
+        let update = UpdateJob { compensation: Some(Some(pay(100, 300))), ..Default::default() };
+        assert!(apply_update(&mut job, update).unwrap());
+        assert_eq!(job.compensation, Some(pay(100, 300)));
+    }
+
+    #[test]
+    fn the_pay_range_can_be_taken_off() {
+        let mut job = sample_job(1, "Rust developer");
+        job.compensation = Some(pay(100, 200));
+
+        assert!(!apply_update(&mut job, UpdateJob::default()).unwrap());
+        assert_eq!(job.compensation, Some(pay(100, 200)));
+
+        let clear = UpdateJob { compensation: Some(None), ..Default::default() };
+        assert!(apply_update(&mut job, clear.clone()).unwrap());
+        assert_eq!(job.compensation, None);
+        assert!(!apply_update(&mut job, clear).unwrap());
+    }
+
+    #[test]
+    fn unchanged_fields_are_not_a_change() {
+        let mut job = sample_job(1, "Rust developer");
+        let update = UpdateJob { title: Some(job.title.clone()), openings: Some(job.openings), ..Default::default() };
+        assert!(!apply_update(&mut job, update).unwrap());
+    }
+}
@@ -0,0 +1,391 @@
+/* full-text search over job titles, descriptions and skills.
+
+   the index is kept in stable memory as postings keyed by (term, job_id), weighted by where in the job the term
+   appears, plus the terms of each job so a job can be taken out of the index again. jobs are indexed when they
+   are created or edited and taken out when they are cancelled */
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use candid::CandidType;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+
+use crate::{get_job, Error, Job, JobStatus, Memory, MEMORY_MANAGER, STORAGE};
+
+/* bump when the way jobs are tokenized changes, the index is rebuilt on the next upgrade */
+const SEARCH_INDEX_VERSION: u32 = 1;
+
+const MIN_TERM_LEN: usize = 2;
+const MAX_TERM_LEN: usize = 40;
+const MAX_TERMS_PER_JOB: usize = 1_000;
+
+const TITLE_WEIGHT: u32 = 3;
+const SKILL_WEIGHT: u32 = 2;
+const DESCRIPTION_WEIGHT: u32 = 1;
+
+const MAX_QUERY_LEN: usize = 200;
+const MAX_QUERY_TERMS: usize = 10;
+/* the most postings one query term reads, so a very common term cannot use up the query */
+const MAX_POSTINGS_PER_TERM: usize = 10_000;
+const DEFAULT_SEARCH_PAGE_SIZE: u32 = 20;
+const MAX_SEARCH_PAGE_SIZE: u32 = 100;
+
+const STOP_WORDS: [&str; 24] = [
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "is", "it", "of", "on", "or", "our", "that",
+    "the", "this", "to", "we", "with", "you",
+];
+
+//a term of at most MAX_TERM_LEN bytes, bounded so it can be part of a tuple key
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct Term(String);
+
+impl Storable for Term {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Term(String::from_utf8(bytes.to_vec()).expect("search term is not utf-8"))
+    }
+
+    const BOUND: Bound = Bound::Bounded { max_size: MAX_TERM_LEN as u32, is_fixed_size: false };
+}
+
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum MatchMode {
+    All, // every term has to match
+    Any, // at least one term has to match
+}
+
+//a search, terms ending in * match every term that starts with them, e.g. dev* finds developer
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SearchJobs {
+    query: String,
+    mode: Option<MatchMode>,   // All unless given
+    status: Option<JobStatus>, // only jobs in this status, by default every job that is not a draft
+    cursor: Option<u32>,       // the next_cursor of the previous page
+    limit: Option<u32>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SearchHit {
+    job: Job,
+    score: u32, // higher is more relevant
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SearchPage {
+    hits: Vec<SearchHit>,
+    next_cursor: Option<u32>,
+}
+
+thread_local! {
+    /*(term, job_id) -> how strongly the term is tied to the job*/
+    static POSTINGS: RefCell<StableBTreeMap<(Term, u64), u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+        ));
+
+    /*the terms each job is indexed under, keyed by (job_id, term)*/
+    static JOB_TERMS: RefCell<StableBTreeMap<(u64, Term), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+        ));
+
+    static INDEX_VERSION: RefCell<Cell<u32, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))), 0).expect("cannot create search index version")
+    );
+}
+
+/* the terms of a piece of text: every run of letters and digits, and words such as c++ or node.js whole */
+fn terms(text: &str) -> Vec<String> {
+    let mut terms = vec![];
+    for word in text.split_whitespace() {
+        let word = word
+            .trim_matches(|c: char| matches!(c, ',' | '.' | ';' | ':' | '!' | '?' | '(' | ')' | '[' | ']' | '"' | '\''))
+            .to_lowercase();
+        if word.chars().any(|c| !c.is_alphanumeric()) {
+            terms.push(word.clone());
+        }
+        terms.extend(word.split(|c: char| !c.is_alphanumeric()).map(String::from));
+    }
+    terms.retain(|term| (MIN_TERM_LEN..=MAX_TERM_LEN).contains(&term.len()) && !STOP_WORDS.contains(&term.as_str()));
+    terms
+}
+
+/* the terms of a job with their weights, the heaviest first */
+fn weighted_terms(job: &Job) -> Vec<(String, u32)> {
+    let mut weights: HashMap<String, u32> = HashMap::new();
+    let fields = [(job.title.as_str(), TITLE_WEIGHT), (job.description.as_str(), DESCRIPTION_WEIGHT)];
+    let skills = job.skills.iter().map(|skill| (skill.as_str(), SKILL_WEIGHT));
+
+    for (text, weight) in fields.into_iter().chain(skills) {
+        for term in terms(text) {
+            let entry = weights.entry(term).or_insert(0);
+            *entry = entry.saturating_add(weight);
+        }
+    }
+
+    let mut weights: Vec<(String, u32)> = weights.into_iter().collect();
+    weights.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    weights.truncate(MAX_TERMS_PER_JOB);
+    weights
+}
+
+/* takes a job out of the index */
+pub(crate) fn remove_job(job_id: u64) {
+    JOB_TERMS.with(|job_terms| {
+        let mut job_terms = job_terms.borrow_mut();
+        let indexed: Vec<(u64, Term)> = job_terms
+            .range((job_id, Term::default())..)
+            .take_while(|((id, _), _)| *id == job_id)
+            .map(|(key, _)| key)
+            .collect();
+
+        POSTINGS.with(|postings| {
+            let mut postings = postings.borrow_mut();
+            for (_, term) in indexed.iter().cloned() {
+                postings.remove(&(term, job_id));
+            }
+        });
+        for key in indexed {
+            job_terms.remove(&key);
+        }
+    });
+}
+
+/* indexes a job as it is now, replacing whatever was indexed for it before */
+pub(crate) fn index_job(job: &Job) {
+    remove_job(job.id);
+
+    POSTINGS.with(|postings| {
+        JOB_TERMS.with(|job_terms| {
+            let mut postings = postings.borrow_mut();
+            let mut job_terms = job_terms.borrow_mut();
+            for (term, weight) in weighted_terms(job) {
+                postings.insert((Term(term.clone()), job.id), weight);
+                job_terms.insert((job.id, Term(term)), ());
+            }
+        })
+    });
+}
+
+/* the index lives in stable memory and survives upgrades. it is only rebuilt when it was built with an
+   older tokenizer, or never built at all, like after the upgrade that added search */
+pub(crate) fn ensure_index() {
+    if INDEX_VERSION.with(|version| *version.borrow().get()) == SEARCH_INDEX_VERSION {
+        return;
+    }
+
+    POSTINGS.with(|postings| postings.borrow_mut().clear_new());
+    JOB_TERMS.with(|job_terms| job_terms.borrow_mut().clear_new());
+    let jobs: Vec<Job> = STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(_, job)| job)
+            .filter(|job| job.status != JobStatus::Cancelled)
+            .collect()
+    });
+    for job in &jobs {
+        index_job(job);
+    }
+
+    INDEX_VERSION
+        .with(|version| version.borrow_mut().set(SEARCH_INDEX_VERSION))
+        .expect("cannot set search index version");
+}
+
+/* the jobs a query term matches with the weight of the match. a prefix term adds up the weights of every term it matches */
+fn matches(term: &str, prefix: bool) -> HashMap<u64, u32> {
+    let mut found: HashMap<u64, u32> = HashMap::new();
+    POSTINGS.with(|postings| {
+        let postings = postings.borrow();
+        let matching = postings
+            .range((Term(term.to_string()), 0)..)
+            .take_while(|((indexed, _), _)| if prefix { indexed.0.starts_with(term) } else { indexed.0 == term })
+            .take(MAX_POSTINGS_PER_TERM);
+        for ((_, job_id), weight) in matching {
+            let entry = found.entry(job_id).or_insert(0);
+            *entry = entry.saturating_add(weight);
+        }
+    });
+    found
+}
+
+#[ic_cdk::query]
+fn search_jobs(request: SearchJobs) -> Result<SearchPage, Error> {
+    if request.query.len() > MAX_QUERY_LEN {
+        return Err(Error::validation("query", &format!("must be at most {} bytes", MAX_QUERY_LEN)));
+    }
+
+    let mut query_terms: Vec<(String, bool)> = vec![];
+    for word in request.query.split_whitespace() {
+        match word.strip_suffix('*') {
+            // no indexed term is longer, and a longer one would not fit in a Term
+            Some(prefix) if prefix.len() > MAX_TERM_LEN => {
+                return Err(Error::validation("query", &format!("prefixes can be at most {} bytes", MAX_TERM_LEN)));
+            }
+            Some(prefix) if prefix.len() >= MIN_TERM_LEN => query_terms.push((prefix.to_lowercase(), true)),
+            _ => query_terms.extend(terms(word).into_iter().map(|term| (term, false))),
+        }
+    }
+    query_terms.sort();
+    query_terms.dedup();
+    if query_terms.is_empty() {
+        return Err(Error::validation("query", "must contain at least one search term"));
+    }
+    if query_terms.len() > MAX_QUERY_TERMS {
+        return Err(Error::validation("query", &format!("must have at most {} terms", MAX_QUERY_TERMS)));
+    }
+
+    let mode = request.mode.unwrap_or(MatchMode::All);
+    let mut scores: HashMap<u64, u32> = HashMap::new();
+    for (index, (term, prefix)) in query_terms.iter().enumerate() {
+        let found = matches(term, *prefix);
+        if mode == MatchMode::All && index > 0 {
+            scores.retain(|job_id, _| found.contains_key(job_id));
+        }
+        for (job_id, weight) in found {
+            if mode == MatchMode::Any || index == 0 || scores.contains_key(&job_id) {
+                let entry = scores.entry(job_id).or_insert(0);
+                *entry = entry.saturating_add(weight);
+            }
+        }
+    }
+
+    let mut ranked: Vec<(u64, u32)> = scores.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+
+    let offset = request.cursor.unwrap_or(0) as usize;
+    let limit = request.limit.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE).clamp(1, MAX_SEARCH_PAGE_SIZE) as usize;
+    let visible = |job: &Job| match request.status {
+        Some(status) => job.status == status,
+        None => job.status != JobStatus::Draft,
+    };
+
+    let mut hits = vec![];
+    let mut next_cursor = None;
+    for (position, (job_id, score)) in ranked.into_iter().enumerate().skip(offset) {
+        if hits.len() == limit {
+            next_cursor = Some(position as u32);
+            break;
+        }
+        if let Some(job) = get_job(job_id).ok().filter(|job| visible(job)) {
+            hits.push(SearchHit { job, score });
+        }
+    }
+
+    Ok(SearchPage { hits, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_job;
+
+    fn search(query: &str) -> Result<Vec<u64>, Error> {
+        let request = SearchJobs { query: query.to_string(), mode: None, status: None, cursor: None, limit: None };
+        search_jobs(request).map(|page| page.hits.into_iter().map(|hit| hit.job.id).collect())
+    }
+
+    fn store(job: &Job) {
+        STORAGE.with(|storage| storage.borrow_mut().insert(job.id, job.clone()));
+    }
+
+    /* every posting has its entry in JOB_TERMS and the other way around */
+    fn assert_consistent() {
+        let mut postings: Vec<(u64, String)> =
+            POSTINGS.with(|postings| postings.borrow().iter().map(|((term, id), _)| (id, term.0)).collect());
+        let mut job_terms: Vec<(u64, String)> =
+            JOB_TERMS.with(|job_terms| job_terms.borrow().iter().map(|((id, term), _)| (id, term.0)).collect());
+        postings.sort();
+        job_terms.sort();
+        assert_eq!(postings, job_terms);
+    }
+
+    #[test]
+    fn rejects_prefixes_longer_than_a_term() {
+        let at_limit = format!("{}*", "x".repeat(MAX_TERM_LEN));
+        assert_eq!(search(&at_limit).unwrap(), Vec::<u64>::new());
+
+        for len in [MAX_TERM_LEN + 1, 49, MAX_QUERY_LEN - 1] {
+            let query = format!("{}*", "x".repeat(len));
+            assert!(matches!(search(&query), Err(Error::ValidationFailed { .. })));
+        }
+    }
+
+    #[test]
+    fn finds_indexed_jobs_by_term_and_prefix() {
+        let mut job = sample_job(1, "Rust developer");
+        job.skills = vec![String::from("motoko")];
+        store(&job);
+        index_job(&job);
+
+        assert_eq!(search("rust").unwrap(), vec![1]);
+        assert_eq!(search("dev*").unwrap(), vec![1]);
+        assert_eq!(search("motoko").unwrap(), vec![1]);
+        assert_eq!(search("python").unwrap(), Vec::<u64>::new());
+        assert_consistent();
+    }
+
+    #[test]
+    fn reindexing_an_edited_job_drops_its_old_terms() {
+        let mut job = sample_job(1, "Rust developer");
+        store(&job);
+        index_job(&job);
+
+        job.title = String::from("Solidity auditor");
+        store(&job);
+        index_job(&job);
+
+        assert_eq!(search("rust").unwrap(), Vec::<u64>::new());
+        assert_eq!(search("auditor").unwrap(), vec![1]);
+        assert_consistent();
+    }
+
+    #[test]
+    fn removed_jobs_leave_nothing_behind() {
+        let first = sample_job(1, "Rust developer");
+        let second = sample_job(2, "Rust auditor");
+        for job in [&first, &second] {
+            store(job);
+            index_job(job);
+        }
+
+        remove_job(1);
+        assert_eq!(search("rust").unwrap(), vec![2]);
+        assert_eq!(JOB_TERMS.with(|job_terms| job_terms.borrow().range((1, Term::default())..(2, Term::default())).count()), 0);
+        assert_consistent();
+    }
+
+    #[test]
+    fn upgrade_builds_the_index_once_and_rebuilds_it_for_a_new_tokenizer() {
+        let mut cancelled = sample_job(2, "Rust auditor");
+        cancelled.status = JobStatus::Cancelled;
+        store(&sample_job(1, "Rust developer"));
+        store(&cancelled);
+
+        // the first upgrade with search indexes the jobs already stored, cancelled ones aside
+        ensure_index();
+        assert_eq!(search("rust").unwrap(), vec![1]);
+        assert_consistent();
+
+        // later upgrades keep the index as it is
+        let postings = POSTINGS.with(|postings| postings.borrow().len());
+        ensure_index();
+        assert_eq!(POSTINGS.with(|postings| postings.borrow().len()), postings);
+
+        // an index written by an older tokenizer is thrown away and built again
+        POSTINGS.with(|postings| postings.borrow_mut().insert((Term(String::from("stale")), 1), 1));
+        JOB_TERMS.with(|job_terms| job_terms.borrow_mut().insert((1, Term(String::from("stale"))), ()));
+        INDEX_VERSION.with(|version| version.borrow_mut().set(SEARCH_INDEX_VERSION - 1)).unwrap();
+        ensure_index();
+        assert_eq!(search("stale").unwrap(), Vec::<u64>::new());
+        assert_eq!(search("developer").unwrap(), vec![1]);
+        assert_eq!(INDEX_VERSION.with(|version| *version.borrow().get()), SEARCH_INDEX_VERSION);
+        assert_consistent();
+    }
+}